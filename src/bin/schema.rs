@@ -1 +1,14 @@
-fn main() {}
+use cosmwasm_schema::write_api;
+
+use oaksecurity_cosmwasm_ctf_02::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, SudoMsg};
+
+fn main() {
+    write_api! {
+        name: "ctf-2",
+        instantiate: InstantiateMsg,
+        execute: ExecuteMsg,
+        query: QueryMsg,
+        sudo: SudoMsg,
+        migrate: MigrateMsg,
+    }
+}
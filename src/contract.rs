@@ -1,25 +1,188 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    coin, to_binary, BankMsg, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128,
+    coin, from_json, to_json_binary, Addr, BankMsg, Binary, Decimal, Deps, DepsMut, Empty, Env,
+    MessageInfo, Order, Reply, Response, StdError, StdResult, Storage, SubMsg, Timestamp, Uint128,
+    WasmMsg,
 };
-use cw_utils::must_pay;
+use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg, MinterResponse};
+use cw20_base::msg::InstantiateMsg as Cw20InstantiateMsg;
+use cw_storage_plus::{Bound, Map};
+use cw_utils::{must_pay, parse_reply_instantiate_data};
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::{UserInfo, VOTING_POWER};
+use crate::events::{
+    AdminChange, AdminEvent, BatchEvent, PenaltyShareEvent, PositionAction, PositionEvent,
+    SudoEvent,
+};
+use crate::ibc::voting_power_updates;
+use crate::msg::{
+    AddressesResponse, BatchOp, CapacityResponse, DepositFor, EarlyUnstakePenaltyResponse,
+    ExecuteMsg, InstantiateMsg, LockTiersResponse, MigrateMsg, PositionResponse, QueryMsg,
+    ReceiptHookMsg, ReceiptTokenResponse, SudoMsg, VestingInfoResponse, WithdrawReceiveMsg,
+    WithdrawalWindowResponse,
+};
+use crate::state::{
+    Config, LockTier, UserInfo, Vesting, VestingSchedule, WithdrawalLimit, ALLOWLIST, CONFIG,
+    FROZEN, PENALTY_INDEX, PENALTY_POOL, PENALTY_REMAINDER, RECEIPT_TOKEN, TOTAL_TOKENS,
+    TOTAL_VOTING_POWER, USER_PENALTY_INDEX, VESTING, VOTING_POWER, WITHDRAWALS,
+};
 
 pub const DENOM: &str = "testcoin";
 pub const LOCK_PERIOD: u64 = 60 * 60 * 24; // One day
+pub const MAX_PENALTY_BPS: u64 = 10_000;
+/// Basis points making up a whole
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+const RECEIPT_TOKEN_REPLY_ID: u64 = 1;
+
+/// Buckets a withdrawal window is split into, bounding the records read per withdrawal
+const WINDOW_BUCKETS: u64 = 24;
+/// Expired buckets removed per withdrawal, more than the one it can add
+const PRUNED_BUCKETS: usize = 2;
+
+// pagination limits for address lists
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
-    _deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
-    _msg: InstantiateMsg,
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
-    Ok(Response::new().add_attribute("action", "instantiate"))
+    if msg.early_unstake_penalty_bps > MAX_PENALTY_BPS {
+        return Err(ContractError::InvalidPenalty {});
+    }
+
+    let owner = match msg.owner {
+        Some(owner) => deps.api.addr_validate(&owner)?,
+        None => info.sender,
+    };
+    let treasury = msg
+        .treasury
+        .map(|treasury| deps.api.addr_validate(&treasury))
+        .transpose()?;
+    if let Some(limit) = &msg.withdrawal_limit {
+        validate_withdrawal_limit(limit)?;
+    }
+
+    CONFIG.save(
+        deps.storage,
+        &Config {
+            owner,
+            early_unstake_penalty_bps: msg.early_unstake_penalty_bps,
+            treasury,
+            lock_period: LOCK_PERIOD,
+            paused: false,
+            stake_unvested: msg.stake_unvested,
+            allowlist_enabled: msg.allowlist_enabled,
+            per_user_cap: msg.per_user_cap,
+            global_cap: msg.global_cap,
+            lock_tiers: vec![],
+            min_stake: Uint128::zero(),
+            withdrawal_limit: msg.withdrawal_limit,
+        },
+    )?;
+    TOTAL_VOTING_POWER.save(deps.storage, &0)?;
+    PENALTY_INDEX.save(deps.storage, &Decimal::zero())?;
+    PENALTY_POOL.save(deps.storage, &Uint128::zero())?;
+    PENALTY_REMAINDER.save(deps.storage, &Uint128::zero())?;
+    TOTAL_TOKENS.save(deps.storage, &Uint128::zero())?;
+
+    let mut response = Response::new().add_attribute("action", "instantiate");
+    if let Some(receipt_token) = msg.receipt_token {
+        let cw20_msg = Cw20InstantiateMsg {
+            name: receipt_token.name,
+            symbol: receipt_token.symbol,
+            decimals: 6,
+            initial_balances: vec![],
+            mint: Some(MinterResponse {
+                minter: env.contract.address.to_string(),
+                cap: None,
+            }),
+            marketing: None,
+        };
+        let instantiate = WasmMsg::Instantiate {
+            admin: None,
+            code_id: receipt_token.code_id,
+            msg: to_json_binary(&cw20_msg)?,
+            funds: vec![],
+            label: "gungnir receipt token".to_string(),
+        };
+        response = response.add_submessage(SubMsg::reply_on_success(
+            instantiate,
+            RECEIPT_TOKEN_REPLY_ID,
+        ));
+    }
+
+    Ok(response)
+}
+
+/// Stores the settings and totals a legacy deployment lacks, with the behaviour it had,
+/// and rewrites its positions in the current format. A current deployment is left as is.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    if CONFIG.may_load(deps.storage)?.is_some() {
+        return Ok(Response::new().add_attribute("action", "migrate"));
+    }
+
+    let owner = deps.api.addr_validate(&msg.owner)?;
+    CONFIG.save(
+        deps.storage,
+        &Config {
+            owner,
+            early_unstake_penalty_bps: 0,
+            treasury: None,
+            lock_period: LOCK_PERIOD,
+            paused: false,
+            stake_unvested: false,
+            allowlist_enabled: false,
+            per_user_cap: None,
+            global_cap: None,
+            lock_tiers: vec![],
+            min_stake: Uint128::zero(),
+            withdrawal_limit: None,
+        },
+    )?;
+
+    // legacy positions load through `StoredUserInfo`, saving them stores `locked_tokens`
+    let users = VOTING_POWER
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut total_tokens = Uint128::zero();
+    let mut total_voting_power = 0;
+    for (addr, user) in &users {
+        total_tokens += user.total_tokens;
+        total_voting_power += user.voting_power;
+        VOTING_POWER.save(deps.storage, addr, user)?;
+    }
+    TOTAL_TOKENS.save(deps.storage, &total_tokens)?;
+    TOTAL_VOTING_POWER.save(deps.storage, &total_voting_power)?;
+    PENALTY_INDEX.save(deps.storage, &Decimal::zero())?;
+    PENALTY_POOL.save(deps.storage, &Uint128::zero())?;
+    PENALTY_REMAINDER.save(deps.storage, &Uint128::zero())?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("positions", users.len().to_string()))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        RECEIPT_TOKEN_REPLY_ID => {
+            let res = parse_reply_instantiate_data(msg)?;
+            let receipt_token = deps.api.addr_validate(&res.contract_address)?;
+            RECEIPT_TOKEN.save(deps.storage, &receipt_token)?;
+
+            Ok(Response::new()
+                .add_attribute("action", "receipt_token_instantiated")
+                .add_attribute("receipt_token", receipt_token))
+        }
+        id => Err(StdError::generic_err(format!("Unknown reply id {id}")).into()),
+    }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -29,95 +192,652 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    if CONFIG.load(deps.storage)?.paused {
+        return Err(ContractError::Paused {});
+    }
+
     match msg {
-        ExecuteMsg::Deposit {} => deposit(deps, info),
-        ExecuteMsg::Withdraw { amount } => withdraw(deps, info, amount),
-        ExecuteMsg::Stake { lock_amount } => stake(deps, env, info, lock_amount),
+        ExecuteMsg::Deposit {} => deposit(deps, env, info),
+        ExecuteMsg::DepositFor { recipient } => deposit_for(deps, env, info, recipient),
+        ExecuteMsg::BatchDepositFor { deposits } => batch_deposit_for(deps, env, info, deposits),
+        ExecuteMsg::Withdraw { amount } => withdraw(deps, env, info, amount),
+        ExecuteMsg::WithdrawAndCall {
+            amount,
+            contract,
+            msg,
+        } => withdraw_and_call(deps, env, info, amount, contract, msg),
+        ExecuteMsg::Batch { ops } => batch(deps, env, info, ops),
+        ExecuteMsg::Receive(wrapper) => receive_receipts(deps, env, info, wrapper),
+        ExecuteMsg::Stake { lock_amount, tier } => stake(deps, env, info, lock_amount, tier),
         ExecuteMsg::Unstake { unlock_amount } => unstake(deps, env, info, unlock_amount),
+        ExecuteMsg::EarlyUnstake { amount } => early_unstake(deps, env, info, amount),
+        ExecuteMsg::ClaimReceipts {} => claim_receipts(deps, env, info),
+        ExecuteMsg::CreateVestingDeposit {
+            beneficiary,
+            schedule,
+        } => create_vesting_deposit(deps, env, info, beneficiary, schedule),
+        ExecuteMsg::SetAllowlistEnabled { enabled } => set_allowlist_enabled(deps, info, enabled),
+        ExecuteMsg::UpdateAllowlist { add, remove } => update_allowlist(deps, info, add, remove),
+        ExecuteMsg::UpdateLockTiers { tiers, min_stake } => {
+            update_lock_tiers(deps, info, tiers, min_stake)
+        }
+        ExecuteMsg::UpdateCaps {
+            per_user_cap,
+            global_cap,
+        } => update_caps(deps, info, per_user_cap, global_cap),
+        ExecuteMsg::UpdateWithdrawalLimit { limit } => update_withdrawal_limit(deps, info, limit),
+        ExecuteMsg::Freeze { user } => freeze(deps, info, user),
+        ExecuteMsg::Unfreeze { user } => unfreeze(deps, info, user),
     }
 }
 
 /// Entry point for user to stake tokens
-pub fn deposit(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+pub fn deposit(mut deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
     // validate denom
-    let amount = must_pay(&info, DENOM).unwrap();
+    let amount = must_pay(&info, DENOM)?;
+
+    let response = credit_deposit(
+        deps.branch(),
+        &env,
+        PositionAction::Deposit,
+        &info.sender,
+        &info.sender,
+        amount,
+    )?;
+    let mint = mint_receipts(deps.storage, &info.sender, amount)?;
+
+    Ok(response
+        .add_attribute("action", "deposit")
+        .add_attribute("user", info.sender)
+        .add_attribute("amount", amount)
+        .add_messages(mint))
+}
+
+/// Entry point for users to deposit tokens into another user's position
+pub fn deposit_for(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    let amount = must_pay(&info, DENOM)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+    // the funder must be allowed to deposit as well as the recipient
+    ensure_allowlisted(deps.storage, &info.sender)?;
+
+    let response = credit_deposit(
+        deps.branch(),
+        &env,
+        PositionAction::DepositFor,
+        &info.sender,
+        &recipient,
+        amount,
+    )?;
+    let mint = mint_receipts(deps.storage, &recipient, amount)?;
+
+    Ok(response
+        .add_attribute("action", "deposit_for")
+        .add_messages(mint))
+}
+
+/// Entry point for users to split the attached funds across several positions
+pub fn batch_deposit_for(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    deposits: Vec<DepositFor>,
+) -> Result<Response, ContractError> {
+    let received = must_pay(&info, DENOM)?;
+    let expected = deposits
+        .iter()
+        .try_fold(Uint128::zero(), |total, deposit| {
+            total.checked_add(deposit.amount)
+        })
+        .map_err(StdError::from)?;
+    if expected != received {
+        return Err(ContractError::FundsMismatch { expected, received });
+    }
+    ensure_allowlisted(deps.storage, &info.sender)?;
+
+    let mut response = Response::new().add_attribute("action", "batch_deposit_for");
+    for deposit in deposits {
+        let recipient = deps.api.addr_validate(&deposit.recipient)?;
+        let res = credit_deposit(
+            deps.branch(),
+            &env,
+            PositionAction::DepositFor,
+            &info.sender,
+            &recipient,
+            deposit.amount,
+        )?;
+        let mint = mint_receipts(deps.storage, &recipient, deposit.amount)?;
+        response = response
+            .add_events(res.events)
+            .add_submessages(res.messages)
+            .add_messages(mint);
+    }
+
+    Ok(response)
+}
+
+/// Entry point for users to run several operations at once, all of them failing
+/// if one does
+pub fn batch(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    ops: Vec<BatchOp>,
+) -> Result<Response, ContractError> {
+    let received = if info.funds.is_empty() {
+        Uint128::zero()
+    } else {
+        must_pay(&info, DENOM)?
+    };
+    let expected = ops
+        .iter()
+        .filter_map(|op| match op {
+            BatchOp::Deposit { amount } => Some(*amount),
+            _ => None,
+        })
+        .try_fold(Uint128::zero(), |total, amount| total.checked_add(amount))
+        .map_err(StdError::from)?;
+    if expected != received {
+        return Err(ContractError::FundsMismatch { expected, received });
+    }
+
+    let mut response = Response::new()
+        .add_attribute("action", "batch")
+        .add_attribute("ops", ops.len().to_string());
+    for (index, op) in ops.into_iter().enumerate() {
+        let op_info = MessageInfo {
+            sender: info.sender.clone(),
+            funds: vec![],
+        };
+        let res = match op {
+            BatchOp::Deposit { amount } => deposit(
+                deps.branch(),
+                env.clone(),
+                MessageInfo {
+                    funds: vec![coin(amount.u128(), DENOM)],
+                    ..op_info
+                },
+            ),
+            BatchOp::Withdraw { amount } => withdraw(deps.branch(), env.clone(), op_info, amount),
+            BatchOp::Stake { lock_amount, tier } => {
+                stake(deps.branch(), env.clone(), op_info, lock_amount, tier)
+            }
+            BatchOp::Unstake { unlock_amount } => {
+                unstake(deps.branch(), env.clone(), op_info, unlock_amount)
+            }
+            BatchOp::EarlyUnstake { amount } => {
+                early_unstake(deps.branch(), env.clone(), op_info, amount)
+            }
+        }
+        .map_err(|error| ContractError::BatchOpFailed {
+            index,
+            error: Box::new(error),
+        })?;
+
+        response = response
+            .add_event(BatchEvent::new(index, res.attributes).to_event())
+            .add_events(res.events)
+            .add_submessages(res.messages);
+    }
+
+    Ok(response)
+}
+
+/// Adds deposited tokens to a user's position, returning the events recording it and
+/// the receipts minted for settled penalties
+fn credit_deposit(
+    deps: DepsMut,
+    env: &Env,
+    action: PositionAction,
+    sender: &Addr,
+    addr: &Addr,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    ensure_allowlisted(deps.storage, addr)?;
 
     // increase total stake
     let mut user = VOTING_POWER
-        .load(deps.storage, &info.sender)
+        .may_load(deps.storage, addr)?
         .unwrap_or_default();
+    let before = user.clone();
+    let share = settle_penalties(deps.storage, addr, &mut user)?;
+
+    let total_tokens = TOTAL_TOKENS.load(deps.storage)?;
+    let capacity = remaining_capacity(deps.storage, &user, total_tokens)?;
+    if let Some(headroom) = capacity.into_iter().flatten().min() {
+        if amount > headroom {
+            return Err(ContractError::CapExceeded { headroom });
+        }
+    }
+
     user.total_tokens += amount;
 
-    VOTING_POWER
-        .save(deps.storage, &info.sender, &user)
-        .unwrap();
+    VOTING_POWER.save(deps.storage, addr, &user)?;
+    TOTAL_TOKENS.save(deps.storage, &(total_tokens + amount))?;
 
-    Ok(Response::new()
-        .add_attribute("action", "deposit")
-        .add_attribute("user", info.sender)
-        .add_attribute("amount", amount))
+    let updates = voting_power_updates(deps.as_ref(), env, addr)?;
+    let event = PositionEvent::new(action, sender, addr, amount, &before, &user);
+    Ok(record_share(deps.storage, share)?
+        .add_event(event.to_event())
+        .add_messages(updates))
+}
+
+/// Headroom left under the global and per-user caps, `None` where uncapped
+fn remaining_capacity(
+    storage: &dyn Storage,
+    user: &UserInfo,
+    total_tokens: Uint128,
+) -> StdResult<[Option<Uint128>; 2]> {
+    let config = CONFIG.load(storage)?;
+    Ok([
+        config
+            .global_cap
+            .map(|cap| cap.saturating_sub(total_tokens)),
+        config
+            .per_user_cap
+            .map(|cap| cap.saturating_sub(user.total_tokens)),
+    ])
 }
 
 /// Entry point for users to withdraw staked tokens
 pub fn withdraw(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     amount: Uint128,
 ) -> Result<Response, ContractError> {
-    // decrease total stake
-    let mut user = VOTING_POWER.load(deps.storage, &info.sender).unwrap();
+    ensure_no_receipts(deps.storage)?;
+    send_withdrawal(deps, env, info.sender.clone(), info.sender, amount)
+}
 
-    // this can panic. (Not fixing it: it is not a bug, but it is messy)
-    user.total_tokens -= amount;
+/// Entry point for user to withdraw tokens straight into a contract, which receives
+/// them along with `msg` wrapped in a `WithdrawReceiveMsg`. The withdrawal is reverted
+/// if the contract fails.
+pub fn withdraw_and_call(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    contract: String,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    ensure_no_receipts(deps.storage)?;
+    call_withdrawal(
+        deps,
+        env,
+        info.sender.clone(),
+        info.sender,
+        amount,
+        contract,
+        msg,
+    )
+}
 
-    // cannot withdraw staked tokens
-    if user.total_tokens.u128() < user.voting_power {
-        return Err(ContractError::InsufficientFunds {  });
+/// Entry point for receipt holders to withdraw or pay an early unstake penalty by
+/// sending receipts back, which are burned
+pub fn receive_receipts(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    let receipt_token = RECEIPT_TOKEN.may_load(deps.storage)?;
+    if receipt_token.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let sender = deps.api.addr_validate(&wrapper.sender)?;
+    let amount = wrapper.amount;
+    match from_json(&wrapper.msg)? {
+        ReceiptHookMsg::Withdraw { owner } => {
+            ensure_not_frozen(deps.storage, &sender)?;
+            let owner = receipt_owner(deps.as_ref(), &info.sender, &sender, owner, amount)?;
+            Ok(send_withdrawal(deps, env, sender, owner, amount)?
+                .add_message(burn_receipts(&info.sender, amount)?))
+        }
+        ReceiptHookMsg::WithdrawAndCall {
+            contract,
+            msg,
+            owner,
+        } => {
+            ensure_not_frozen(deps.storage, &sender)?;
+            let owner = receipt_owner(deps.as_ref(), &info.sender, &sender, owner, amount)?;
+            Ok(
+                call_withdrawal(deps, env, sender, owner, amount, contract, msg)?
+                    .add_message(burn_receipts(&info.sender, amount)?),
+            )
+        }
+        ReceiptHookMsg::EarlyUnstake { amount: unstaked } => {
+            unstake_early(deps, env, sender, unstaked, Some(amount))
+        }
     }
+}
 
-    VOTING_POWER
-        .save(deps.storage, &info.sender, &user)
-        .unwrap();
+/// Position redeemed receipts are withdrawn from. The holder's own position gives up any
+/// tokens, another one only those its owner holds no receipts for, as the rest backs
+/// receipts the owner can still redeem.
+fn receipt_owner(
+    deps: Deps,
+    receipt_token: &Addr,
+    sender: &Addr,
+    owner: Option<String>,
+    amount: Uint128,
+) -> Result<Addr, ContractError> {
+    let Some(owner) = owner else {
+        return Ok(sender.clone());
+    };
+    let owner = deps.api.addr_validate(&owner)?;
+    if owner == *sender {
+        return Ok(owner);
+    }
+
+    let user = VOTING_POWER
+        .may_load(deps.storage, &owner)?
+        .unwrap_or_default();
+    let unreceipted = VESTING
+        .may_load(deps.storage, &owner)?
+        .map(|vesting| vesting.unreceipted())
+        .unwrap_or_default();
+    let held: BalanceResponse = deps.querier.query_wasm_smart(
+        receipt_token,
+        &Cw20QueryMsg::Balance {
+            address: owner.to_string(),
+        },
+    )?;
+    let available = user
+        .total_tokens
+        .saturating_sub(unreceipted)
+        .saturating_sub(held.balance);
+    if amount > available {
+        return Err(ContractError::PositionBacked {
+            owner: owner.to_string(),
+            available,
+        });
+    }
+    Ok(owner)
+}
+
+/// Sends tokens withdrawn from `owner`'s position to `sender`
+fn send_withdrawal(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    owner: Addr,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let response = debit_withdrawal(deps, &env, &sender, &owner, amount)?;
 
     let msg = BankMsg::Send {
-        to_address: info.sender.to_string(),
+        to_address: sender.to_string(),
         amount: vec![coin(amount.u128(), DENOM)],
     };
 
-    Ok(Response::new()
+    Ok(response
         .add_attribute("action", "withdraw")
-        .add_attribute("user", info.sender)
+        .add_attribute("user", sender)
+        .add_attribute("owner", owner)
         .add_attribute("amount", amount)
         .add_message(msg))
 }
 
+/// Sends tokens withdrawn from `owner`'s position into `contract` along with the
+/// sender's payload
+fn call_withdrawal(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    owner: Addr,
+    amount: Uint128,
+    contract: String,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    let contract_addr = deps.api.addr_validate(&contract)?;
+    let response = debit_withdrawal(deps, &env, &sender, &owner, amount)?;
+
+    let receive_msg = WithdrawReceiveMsg {
+        sender: sender.to_string(),
+        amount,
+        msg,
+    }
+    .into_cosmos_msg(&contract_addr, vec![coin(amount.u128(), DENOM)])?;
+
+    Ok(response
+        .add_attribute("action", "withdraw_and_call")
+        .add_attribute("user", sender)
+        .add_attribute("owner", owner)
+        .add_attribute("amount", amount)
+        .add_attribute("contract", contract_addr)
+        .add_message(receive_msg))
+}
+
+/// Adds a withdrawal to the window, rejecting it beyond the withdrawal limit
+fn record_withdrawal(
+    storage: &mut dyn Storage,
+    config: &Config,
+    now: Timestamp,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let Some(limit) = &config.withdrawal_limit else {
+        return Ok(());
+    };
+
+    let used = window_withdrawals(storage, limit, now)?;
+    let remaining = window_remaining(storage, limit, used)?;
+    if amount > remaining {
+        return Err(ContractError::WithdrawalLimitExceeded {
+            max_bps: limit.max_bps,
+            window: limit.window,
+            remaining,
+        });
+    }
+
+    let bucket_len = (limit.window / WINDOW_BUCKETS).max(1);
+    let bucket = now.seconds() - now.seconds() % bucket_len;
+    WITHDRAWALS.update(storage, bucket, |total| -> StdResult<_> {
+        Ok(total.unwrap_or_default() + amount)
+    })?;
+
+    if let Some(start) = window_start(limit, now) {
+        let expired = WITHDRAWALS
+            .keys(
+                storage,
+                None,
+                Some(Bound::inclusive(start)),
+                Order::Ascending,
+            )
+            .take(PRUNED_BUCKETS)
+            .collect::<StdResult<Vec<_>>>()?;
+        for bucket in expired {
+            WITHDRAWALS.remove(storage, bucket);
+        }
+    }
+    Ok(())
+}
+
+/// Total withdrawn in buckets starting within the window, which lets a withdrawal
+/// leave it up to one bucket early
+fn window_withdrawals(
+    storage: &dyn Storage,
+    limit: &WithdrawalLimit,
+    now: Timestamp,
+) -> StdResult<Uint128> {
+    let start = window_start(limit, now).map(Bound::exclusive);
+    WITHDRAWALS
+        .range(storage, start, None, Order::Ascending)
+        .map(|item| item.map(|(_, amount)| amount))
+        .sum()
+}
+
+/// Last second before the window, if the window started after time zero
+fn window_start(limit: &WithdrawalLimit, now: Timestamp) -> Option<u64> {
+    now.seconds().checked_sub(limit.window)
+}
+
+/// Tokens that can still be withdrawn, the limit applying to the tokens held
+/// before the window's withdrawals
+fn window_remaining(
+    storage: &dyn Storage,
+    limit: &WithdrawalLimit,
+    used: Uint128,
+) -> StdResult<Uint128> {
+    let allowed =
+        (TOTAL_TOKENS.load(storage)? + used).multiply_ratio(limit.max_bps, BPS_DENOMINATOR);
+    Ok(allowed.saturating_sub(used))
+}
+
+/// Rejects direct withdrawals once receipts are minted for deposits
+fn ensure_no_receipts(storage: &dyn Storage) -> Result<(), ContractError> {
+    if RECEIPT_TOKEN.exists(storage) {
+        return Err(ContractError::ReceiptsRequired {});
+    }
+    Ok(())
+}
+
+/// Burns receipts sent back to the contract
+fn burn_receipts(receipt_token: &Addr, amount: Uint128) -> StdResult<WasmMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: receipt_token.to_string(),
+        msg: to_json_binary(&Cw20ExecuteMsg::Burn { amount })?,
+        funds: vec![],
+    })
+}
+
+/// Mints receipts for tokens added to a position, if a receipt token is set
+fn mint_receipts(
+    storage: &dyn Storage,
+    recipient: &Addr,
+    amount: Uint128,
+) -> StdResult<Option<WasmMsg>> {
+    RECEIPT_TOKEN
+        .may_load(storage)?
+        .map(|receipt_token| {
+            Ok(WasmMsg::Execute {
+                contract_addr: receipt_token.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Mint {
+                    recipient: recipient.to_string(),
+                    amount,
+                })?,
+                funds: vec![],
+            })
+        })
+        .transpose()
+}
+
+/// Takes tokens `sender` withdraws out of `owner`'s position, returning the events
+/// recording it and the receipts minted for settled penalties
+fn debit_withdrawal(
+    deps: DepsMut,
+    env: &Env,
+    sender: &Addr,
+    owner: &Addr,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    ensure_not_frozen(deps.storage, owner)?;
+    let config = CONFIG.load(deps.storage)?;
+
+    // decrease total stake
+    let mut user = VOTING_POWER.load(deps.storage, owner)?;
+    let before = user.clone();
+    let share = settle_penalties(deps.storage, owner, &mut user)?;
+    let unvested = unvested_tokens(deps.storage, owner, env.block.time)?;
+
+    // cannot withdraw staked or unvested tokens
+    if amount > user.withdrawable(unvested, config.stake_unvested) {
+        return Err(ContractError::InsufficientFunds {  });
+    }
+
+    record_withdrawal(deps.storage, &config, env.block.time, amount)?;
+    user.total_tokens -= amount;
+
+    VOTING_POWER.save(deps.storage, owner, &user)?;
+    TOTAL_TOKENS.update(deps.storage, |total| -> StdResult<_> { Ok(total - amount) })?;
+
+    let updates = voting_power_updates(deps.as_ref(), env, owner)?;
+    let event = PositionEvent::new(
+        PositionAction::Withdraw,
+        sender,
+        owner,
+        amount,
+        &before,
+        &user,
+    );
+    Ok(record_share(deps.storage, share)?
+        .add_event(event.to_event())
+        .add_messages(updates))
+}
+
 /// Entry point for user to stake tokens for voting power
 pub fn stake(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     lock_amount: u128,
+    tier: Option<u32>,
 ) -> Result<Response, ContractError> {
-    // increase voting power
-    let mut user = VOTING_POWER.load(deps.storage, &info.sender).unwrap();
+    ensure_not_frozen(deps.storage, &info.sender)?;
+    let config = CONFIG.load(deps.storage)?;
 
-    user.voting_power += lock_amount;
+    // increase voting power
+    let mut user = VOTING_POWER.load(deps.storage, &info.sender)?;
+    let before = user.clone();
+    let share = settle_penalties(deps.storage, &info.sender, &mut user)?;
+    let unvested = unvested_tokens(deps.storage, &info.sender, env.block.time)?;
 
     // cannot stake more than total tokens
-    if user.voting_power > user.total_tokens.u128() {
+    if Uint128::new(lock_amount) > user.stakeable(unvested, config.stake_unvested) {
         return Err(ContractError::InsufficientFunds {  });
     }
 
-    user.released_time = env.block.time.plus_seconds(LOCK_PERIOD);
+    if Uint128::new(lock_amount) < config.min_stake {
+        return Err(ContractError::StakeTooSmall {
+            min_stake: config.min_stake,
+        });
+    }
 
-    VOTING_POWER
-        .save(deps.storage, &info.sender, &user)
-        .unwrap();
+    let (duration, multiplier) = match tier {
+        None => (config.lock_period, Decimal::one()),
+        Some(tier) => config
+            .lock_tiers
+            .get(tier as usize)
+            .map(|lock_tier| (lock_tier.duration, lock_tier.multiplier))
+            .ok_or(ContractError::UnknownLockTier { tier })?,
+    };
+    let voting_power = Uint128::new(lock_amount).mul_floor(multiplier).u128();
 
-    Ok(Response::new()
+    user.locked_tokens += Uint128::new(lock_amount);
+    user.voting_power += voting_power;
+
+    // a shorter tier never shortens an existing lock
+    user.released_time = user
+        .released_time
+        .max(env.block.time.plus_seconds(duration));
+
+    VOTING_POWER.save(deps.storage, &info.sender, &user)?;
+    TOTAL_VOTING_POWER.update(deps.storage, |total| -> StdResult<_> {
+        Ok(total + voting_power)
+    })?;
+
+    let response = record_share(deps.storage, share)?;
+    let updates = voting_power_updates(deps.as_ref(), &env, &info.sender)?;
+    let event = PositionEvent::new(
+        PositionAction::Stake,
+        &info.sender,
+        &info.sender,
+        Uint128::new(lock_amount),
+        &before,
+        &user,
+    );
+
+    Ok(response
         .add_attribute("action", "stake")
         .add_attribute("lock_amount", lock_amount.to_string())
-        .add_attribute("user.voting_power", user.voting_power.to_string()))
+        .add_attribute("user.voting_power", user.voting_power.to_string())
+        .add_event(event.to_event())
+        .add_messages(updates))
 }
 
 /// Entry point for users to decrease voting power
@@ -127,50 +847,861 @@ pub fn unstake(
     info: MessageInfo,
     unlock_amount: u128,
 ) -> Result<Response, ContractError> {
+    ensure_not_frozen(deps.storage, &info.sender)?;
     // decrease voting power
-    let mut user = VOTING_POWER.load(deps.storage, &info.sender).unwrap();
+    let mut user = VOTING_POWER.load(deps.storage, &info.sender)?;
+    let before = user.clone();
+    let share = settle_penalties(deps.storage, &info.sender, &mut user)?;
 
     // check release time
-    if env.block.time < user.released_time {
+    if !user.is_unlocked(env.block.time) {
         return Err(ContractError::Unauthorized {});
     }
 
     // a fix for overflow
-    if user.voting_power < unlock_amount {
+    if user.locked_tokens.u128() < unlock_amount {
         return Err(ContractError::InsufficientVotingPower {  });
     }
 
-    user.voting_power -= unlock_amount;
+    let voting_power = user.voting_power_of(Uint128::new(unlock_amount));
+    user.locked_tokens -= Uint128::new(unlock_amount);
+    user.voting_power -= voting_power;
 
-    VOTING_POWER
-        .save(deps.storage, &info.sender, &user)
-        .unwrap();
+    VOTING_POWER.save(deps.storage, &info.sender, &user)?;
+    TOTAL_VOTING_POWER.update(deps.storage, |total| -> StdResult<_> {
+        Ok(total - voting_power)
+    })?;
 
-    Ok(Response::new()
+    let response = record_share(deps.storage, share)?;
+    let updates = voting_power_updates(deps.as_ref(), &env, &info.sender)?;
+    let event = PositionEvent::new(
+        PositionAction::Unstake,
+        &info.sender,
+        &info.sender,
+        Uint128::new(unlock_amount),
+        &before,
+        &user,
+    );
+
+    Ok(response
         .add_attribute("action", "unstake")
         .add_attribute("unlock_amount", unlock_amount.to_string())
-        .add_attribute("user.voting_power", user.voting_power.to_string()))
+        .add_attribute("user.voting_power", user.voting_power.to_string())
+        .add_event(event.to_event())
+        .add_messages(updates))
+}
+
+/// Entry point for users to decrease voting power before the lock is released,
+/// at the cost of a penalty proportional to the remaining lock time
+pub fn early_unstake(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: u128,
+) -> Result<Response, ContractError> {
+    unstake_early(deps, env, info.sender, amount, None)
+}
+
+/// Early unstakes from `sender`'s position. With a receipt token, the penalty leaves the
+/// position so `returned` receipts must cover it, the rest being sent back.
+fn unstake_early(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    amount: u128,
+    returned: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    ensure_not_frozen(deps.storage, &sender)?;
+    let config = CONFIG.load(deps.storage)?;
+    let mut user = VOTING_POWER.load(deps.storage, &sender)?;
+    let before = user.clone();
+    let share = settle_penalties(deps.storage, &sender, &mut user)?;
+
+    if user.locked_tokens.u128() < amount {
+        return Err(ContractError::InsufficientVotingPower {});
+    }
+
+    let mut penalty = early_unstake_penalty(&config, &user, env.block.time, amount);
+    let voting_power = user.voting_power_of(Uint128::new(amount));
+    user.locked_tokens -= Uint128::new(amount);
+    user.voting_power -= voting_power;
+
+    let total_voting_power = TOTAL_VOTING_POWER.load(deps.storage)? - voting_power;
+    TOTAL_VOTING_POWER.save(deps.storage, &total_voting_power)?;
+
+    // the user's own remaining stake does not share in the penalty
+    let others_voting_power = total_voting_power - user.voting_power;
+    if config.treasury.is_none() && others_voting_power == 0 {
+        // nobody left to receive it
+        penalty = Uint128::zero();
+    }
+
+    let mut response = record_share(deps.storage, share)?;
+    if let Some(receipt_token) = RECEIPT_TOKEN.may_load(deps.storage)? {
+        let returned = returned.unwrap_or_default();
+        if returned < penalty {
+            return Err(ContractError::PenaltyReceiptsRequired { penalty });
+        }
+        if !penalty.is_zero() {
+            response = response.add_message(burn_receipts(&receipt_token, penalty)?);
+        }
+        if returned > penalty {
+            response = response.add_message(WasmMsg::Execute {
+                contract_addr: receipt_token.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: sender.to_string(),
+                    amount: returned - penalty,
+                })?,
+                funds: vec![],
+            });
+        }
+    }
+
+    if !penalty.is_zero() {
+        match &config.treasury {
+            Some(treasury) => {
+                TOTAL_TOKENS.update(deps.storage, |total| -> StdResult<_> {
+                    Ok(total - penalty)
+                })?;
+                response = response.add_message(BankMsg::Send {
+                    to_address: treasury.to_string(),
+                    amount: vec![coin(penalty.u128(), DENOM)],
+                });
+            }
+            None => {
+                // the index rounds down, so what it leaves out is carried forward
+                let distributed = penalty
+                    + PENALTY_REMAINDER
+                        .may_load(deps.storage)?
+                        .unwrap_or_default();
+                let per_power = Decimal::checked_from_ratio(distributed, others_voting_power)?;
+                let remainder = distributed - Uint128::new(others_voting_power) * per_power;
+                PENALTY_REMAINDER.save(deps.storage, &remainder)?;
+                let index = PENALTY_INDEX.load(deps.storage)? + per_power;
+                PENALTY_INDEX.save(deps.storage, &index)?;
+                USER_PENALTY_INDEX.save(deps.storage, &sender, &index)?;
+                PENALTY_POOL.update(deps.storage, |pool| -> StdResult<_> { Ok(pool + penalty) })?;
+            }
+        }
+    }
+
+    user.total_tokens -= penalty;
+
+    VOTING_POWER.save(deps.storage, &sender, &user)?;
+
+    let updates = voting_power_updates(deps.as_ref(), &env, &sender)?;
+    let event = PositionEvent::new(
+        PositionAction::EarlyUnstake,
+        &sender,
+        &sender,
+        Uint128::new(amount),
+        &before,
+        &user,
+    );
+
+    Ok(response
+        .add_event(event.to_event())
+        .add_attribute("action", "early_unstake")
+        .add_attribute("user", sender)
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("penalty", penalty)
+        .add_attribute("user.voting_power", user.voting_power.to_string())
+        .add_messages(updates))
+}
+
+/// Entry point for the owner to deposit tokens on behalf of a user, vesting under a schedule
+pub fn create_vesting_deposit(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    beneficiary: String,
+    schedule: VestingSchedule,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    if schedule.cliff > schedule.duration {
+        return Err(ContractError::InvalidVestingSchedule {});
+    }
+
+    let amount = must_pay(&info, DENOM)?;
+    let beneficiary = deps.api.addr_validate(&beneficiary)?;
+
+    // a fully vested schedule can be replaced, once receipts are minted for the rest of it
+    if !unvested_tokens(deps.storage, &beneficiary, env.block.time)?.is_zero() {
+        return Err(ContractError::VestingExists {});
+    }
+    let previous = vested_receipts(deps.storage, &beneficiary, env.block.time)?;
+
+    let vesting = Vesting {
+        amount,
+        schedule,
+        receipted: Uint128::zero(),
+    };
+    VESTING.save(deps.storage, &beneficiary, &vesting)?;
+    let response = credit_deposit(
+        deps.branch(),
+        &env,
+        PositionAction::VestingDeposit,
+        &info.sender,
+        &beneficiary,
+        amount,
+    )?;
+    // receipts are minted as the tokens vest
+    let mint = vested_receipts(deps.storage, &beneficiary, env.block.time)?;
+
+    Ok(response
+        .add_attribute("action", "create_vesting_deposit")
+        .add_attribute("beneficiary", beneficiary)
+        .add_attribute("amount", amount)
+        .add_messages(previous)
+        .add_messages(mint))
+}
+
+/// Entry point for users to mint the receipts owed for settled penalty shares and
+/// vested tokens
+pub fn claim_receipts(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut user = VOTING_POWER.load(deps.storage, &info.sender)?;
+    let share = settle_penalties(deps.storage, &info.sender, &mut user)?;
+    VOTING_POWER.save(deps.storage, &info.sender, &user)?;
+    let vested = vested_receipts(deps.storage, &info.sender, env.block.time)?;
+    let updates = voting_power_updates(deps.as_ref(), &env, &info.sender)?;
+
+    Ok(record_share(deps.storage, share)?
+        .add_attribute("action", "claim_receipts")
+        .add_attribute("user", info.sender)
+        .add_messages(vested)
+        .add_messages(updates))
+}
+
+/// Mints receipts for the tokens of a vesting deposit vested since receipts were last
+/// minted for it
+fn vested_receipts(
+    storage: &mut dyn Storage,
+    addr: &Addr,
+    now: Timestamp,
+) -> StdResult<Option<WasmMsg>> {
+    let Some(mut vesting) = VESTING.may_load(storage, addr)? else {
+        return Ok(None);
+    };
+    let vested = vesting.amount - vesting.unvested(now);
+    if vested <= vesting.receipted {
+        return Ok(None);
+    }
+
+    let owed = vested - vesting.receipted;
+    vesting.receipted = vested;
+    VESTING.save(storage, addr, &vesting)?;
+    mint_receipts(storage, addr, owed)
+}
+
+/// Entry point for the owner to turn the deposit allowlist on or off
+pub fn set_allowlist_enabled(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.allowlist_enabled = enabled;
+        Ok(config)
+    })?;
+
+    let event = AdminEvent::new(&info.sender, AdminChange::SetAllowlistEnabled { enabled });
+
+    Ok(Response::new()
+        .add_attribute("action", "set_allowlist_enabled")
+        .add_attribute("enabled", enabled.to_string())
+        .add_event(event.to_event()))
+}
+
+/// Entry point for the owner to add and remove allowlisted depositors
+pub fn update_allowlist(
+    deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let added = add
+        .iter()
+        .map(|address| deps.api.addr_validate(address))
+        .collect::<StdResult<Vec<_>>>()?;
+    let removed = remove
+        .iter()
+        .map(|address| deps.api.addr_validate(address))
+        .collect::<StdResult<Vec<_>>>()?;
+    for addr in &added {
+        ALLOWLIST.save(deps.storage, addr, &Empty {})?;
+    }
+    for addr in &removed {
+        ALLOWLIST.remove(deps.storage, addr);
+    }
+
+    let event = AdminEvent::new(
+        &info.sender,
+        AdminChange::UpdateAllowlist { added, removed },
+    );
+
+    Ok(Response::new()
+        .add_attribute("action", "update_allowlist")
+        .add_attribute("added", add.len().to_string())
+        .add_attribute("removed", remove.len().to_string())
+        .add_event(event.to_event()))
+}
+
+/// Entry point for the owner to replace the lock tiers and the minimum stake
+pub fn update_lock_tiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    tiers: Vec<LockTier>,
+    min_stake: Uint128,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    if tiers
+        .iter()
+        .any(|tier| tier.duration == 0 || tier.multiplier < Decimal::one())
+    {
+        return Err(ContractError::InvalidLockTier {});
+    }
+
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.lock_tiers = tiers.clone();
+        config.min_stake = min_stake;
+        Ok(config)
+    })?;
+
+    let event = AdminEvent::new(
+        &info.sender,
+        AdminChange::UpdateLockTiers { tiers, min_stake },
+    );
+
+    Ok(Response::new()
+        .add_attribute("action", "update_lock_tiers")
+        .add_attribute("min_stake", min_stake)
+        .add_event(event.to_event()))
+}
+
+/// Entry point for the owner to change the deposit caps, `None` removing a cap
+pub fn update_caps(
+    deps: DepsMut,
+    info: MessageInfo,
+    per_user_cap: Option<Uint128>,
+    global_cap: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.per_user_cap = per_user_cap;
+        config.global_cap = global_cap;
+        Ok(config)
+    })?;
+
+    let event = AdminEvent::new(
+        &info.sender,
+        AdminChange::UpdateCaps {
+            per_user_cap,
+            global_cap,
+        },
+    );
+
+    Ok(Response::new()
+        .add_attribute("action", "update_caps")
+        .add_event(event.to_event()))
+}
+
+/// Entry point for the owner to change the withdrawal limit, `None` removing it
+pub fn update_withdrawal_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    limit: Option<WithdrawalLimit>,
+) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    if let Some(limit) = &limit {
+        validate_withdrawal_limit(limit)?;
+    }
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.withdrawal_limit = limit.clone();
+        Ok(config)
+    })?;
+
+    let event = AdminEvent::new(&info.sender, AdminChange::UpdateWithdrawalLimit { limit });
+
+    Ok(Response::new()
+        .add_attribute("action", "update_withdrawal_limit")
+        .add_event(event.to_event()))
+}
+
+fn validate_withdrawal_limit(limit: &WithdrawalLimit) -> Result<(), ContractError> {
+    if limit.window == 0 || limit.max_bps > BPS_DENOMINATOR {
+        return Err(ContractError::InvalidWithdrawalLimit {});
+    }
+    Ok(())
+}
+
+/// Entry point for the owner to block withdrawals and (un)staking for a user
+pub fn freeze(deps: DepsMut, info: MessageInfo, user: String) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let user_addr = deps.api.addr_validate(&user)?;
+    FROZEN.save(deps.storage, &user_addr, &Empty {})?;
+
+    let event = AdminEvent::new(
+        &info.sender,
+        AdminChange::Freeze {
+            user: user_addr.clone(),
+        },
+    );
+
+    Ok(Response::new()
+        .add_attribute("action", "freeze")
+        .add_attribute("user", user_addr)
+        .add_event(event.to_event()))
+}
+
+/// Entry point for the owner to lift a freeze
+pub fn unfreeze(deps: DepsMut, info: MessageInfo, user: String) -> Result<Response, ContractError> {
+    ensure_owner(deps.storage, &info.sender)?;
+    let user_addr = deps.api.addr_validate(&user)?;
+    FROZEN.remove(deps.storage, &user_addr);
+
+    let event = AdminEvent::new(
+        &info.sender,
+        AdminChange::Unfreeze {
+            user: user_addr.clone(),
+        },
+    );
+
+    Ok(Response::new()
+        .add_attribute("action", "unfreeze")
+        .add_attribute("user", user_addr)
+        .add_event(event.to_event()))
+}
+
+fn ensure_owner(storage: &dyn Storage, sender: &Addr) -> Result<(), ContractError> {
+    if *sender != CONFIG.load(storage)?.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+/// Rejects `addr` if the allowlist is enabled and does not include it
+fn ensure_allowlisted(storage: &dyn Storage, addr: &Addr) -> Result<(), ContractError> {
+    if CONFIG.load(storage)?.allowlist_enabled && !ALLOWLIST.has(storage, addr) {
+        return Err(ContractError::NotAllowlisted {
+            address: addr.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_not_frozen(storage: &dyn Storage, addr: &Addr) -> Result<(), ContractError> {
+    if FROZEN.has(storage, addr) {
+        return Err(ContractError::Frozen {
+            address: addr.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Tokens deposited for the user under a vesting schedule that have not vested yet
+fn unvested_tokens(storage: &dyn Storage, addr: &Addr, now: Timestamp) -> StdResult<Uint128> {
+    Ok(VESTING
+        .may_load(storage, addr)?
+        .map(|vesting| vesting.unvested(now))
+        .unwrap_or_default())
+}
+
+/// Penalty for unstaking `amount` at `now`, scaled by the remaining lock time
+/// relative to the longest lock available
+fn early_unstake_penalty(
+    config: &Config,
+    user: &UserInfo,
+    now: Timestamp,
+    amount: u128,
+) -> Uint128 {
+    let max_lock_period = config.max_lock_period();
+    let remaining = user.seconds_until_unlock(now).min(max_lock_period);
+    if remaining == 0 {
+        return Uint128::zero();
+    }
+
+    Uint128::new(amount).multiply_ratio(
+        u128::from(config.early_unstake_penalty_bps) * u128::from(remaining),
+        u128::from(BPS_DENOMINATOR) * u128::from(max_lock_period),
+    )
+}
+
+/// Share of distributed penalties the user accrued since their last settlement
+pub(crate) fn pending_penalties(
+    storage: &dyn Storage,
+    addr: &Addr,
+    user: &UserInfo,
+) -> StdResult<Uint128> {
+    let index = PENALTY_INDEX.may_load(storage)?.unwrap_or_default();
+    let user_index = USER_PENALTY_INDEX.may_load(storage, addr)?.unwrap_or(index);
+    Ok(Uint128::new(user.voting_power) * (index - user_index))
+}
+
+/// Credits the user's share of distributed penalties to their total tokens
+fn settle_penalties(
+    storage: &mut dyn Storage,
+    addr: &Addr,
+    user: &mut UserInfo,
+) -> StdResult<Option<PenaltyShareEvent>> {
+    let share = pending_penalties(storage, addr, user)?;
+    if !share.is_zero() {
+        user.total_tokens += share;
+        PENALTY_POOL.update(storage, |pool| -> StdResult<_> { Ok(pool - share) })?;
+    }
+
+    let index = PENALTY_INDEX.may_load(storage)?.unwrap_or_default();
+    USER_PENALTY_INDEX.save(storage, addr, &index)?;
+    Ok((!share.is_zero()).then(|| PenaltyShareEvent {
+        user: addr.clone(),
+        amount: share,
+    }))
+}
+
+/// Event and receipts recording a penalty share credited by `settle_penalties`
+fn record_share(storage: &dyn Storage, share: Option<PenaltyShareEvent>) -> StdResult<Response> {
+    let Some(share) = share else {
+        return Ok(Response::new());
+    };
+    let mint = mint_receipts(storage, &share.user, share.amount)?;
+    Ok(Response::new()
+        .add_event(share.to_event())
+        .add_messages(mint))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::ForceUnlock { user } => force_unlock(deps, env, user),
+        SudoMsg::SetLockPeriod { lock_period } => set_lock_period(deps, lock_period),
+        SudoMsg::SetPaused { paused } => set_paused(deps, paused),
+        SudoMsg::ForceReturn { user } => force_return(deps, env, user),
+    }
+}
+
+/// Releases a user's lock so their voting power can be unstaked right away
+pub fn force_unlock(deps: DepsMut, env: Env, user: String) -> Result<Response, ContractError> {
+    let user_addr = deps.api.addr_validate(&user)?;
+    let mut user = VOTING_POWER.load(deps.storage, &user_addr)?;
+    let before = user.clone();
+
+    user.released_time = env.block.time;
+    VOTING_POWER.save(deps.storage, &user_addr, &user)?;
+
+    let event = PositionEvent::new(
+        PositionAction::ForceUnlock,
+        &env.contract.address,
+        &user_addr,
+        Uint128::zero(),
+        &before,
+        &user,
+    );
+
+    Ok(Response::new()
+        .add_event(SudoEvent::ForceUnlock { user: user_addr }.to_event())
+        .add_event(event.to_event()))
+}
+
+/// Changes the lock period applied to future stakes
+pub fn set_lock_period(deps: DepsMut, lock_period: u64) -> Result<Response, ContractError> {
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.lock_period = lock_period;
+        Ok(config)
+    })?;
+
+    Ok(Response::new().add_event(SudoEvent::SetLockPeriod { lock_period }.to_event()))
+}
+
+/// Suspends or resumes all user operations
+pub fn set_paused(deps: DepsMut, paused: bool) -> Result<Response, ContractError> {
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.paused = paused;
+        Ok(config)
+    })?;
+
+    Ok(Response::new().add_event(SudoEvent::SetPaused { paused }.to_event()))
+}
+
+/// Sends all of a user's tokens back regardless of locks and clears their position.
+/// Tokens backing receipts stay in the position instead, released for their holders.
+pub fn force_return(deps: DepsMut, env: Env, user: String) -> Result<Response, ContractError> {
+    let user_addr = deps.api.addr_validate(&user)?;
+    let mut user = VOTING_POWER.load(deps.storage, &user_addr)?;
+    let before = user.clone();
+    let share = settle_penalties(deps.storage, &user_addr, &mut user)?;
+    let mut response = record_share(deps.storage, share)?;
+
+    TOTAL_VOTING_POWER.update(deps.storage, |total| -> StdResult<_> {
+        Ok(total - user.voting_power)
+    })?;
+
+    let (returned, after) = if RECEIPT_TOKEN.exists(deps.storage) {
+        // receipts still claim the tokens, including those yet to vest
+        let unreceipted = VESTING
+            .may_load(deps.storage, &user_addr)?
+            .map(|vesting| vesting.unreceipted())
+            .unwrap_or_default();
+        VESTING.remove(deps.storage, &user_addr);
+        if !unreceipted.is_zero() {
+            response = response.add_messages(mint_receipts(deps.storage, &user_addr, unreceipted)?);
+        }
+
+        user.locked_tokens = Uint128::zero();
+        user.voting_power = 0;
+        user.released_time = env.block.time;
+        VOTING_POWER.save(deps.storage, &user_addr, &user)?;
+        (Uint128::zero(), user)
+    } else {
+        VOTING_POWER.remove(deps.storage, &user_addr);
+        USER_PENALTY_INDEX.remove(deps.storage, &user_addr);
+        VESTING.remove(deps.storage, &user_addr);
+        TOTAL_TOKENS.update(deps.storage, |total| -> StdResult<_> {
+            Ok(total - user.total_tokens)
+        })?;
+
+        if !user.total_tokens.is_zero() {
+            response = response.add_message(BankMsg::Send {
+                to_address: user_addr.to_string(),
+                amount: vec![coin(user.total_tokens.u128(), DENOM)],
+            });
+        }
+        (user.total_tokens, UserInfo::default())
+    };
+
+    let updates = voting_power_updates(deps.as_ref(), &env, &user_addr)?;
+    let event = PositionEvent::new(
+        PositionAction::ForceReturn,
+        &env.contract.address,
+        &user_addr,
+        returned,
+        &before,
+        &after,
+    );
+
+    Ok(response
+        .add_event(
+            SudoEvent::ForceReturn {
+                user: user_addr,
+                amount: returned,
+            }
+            .to_event(),
+        )
+        .add_event(event.to_event())
+        .add_messages(updates))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
-        QueryMsg::GetUser { user } => to_binary(&get_user(deps, user)?),
-        QueryMsg::GetVotingPower { user } => to_binary(&get_voting_power(deps, user)?),
+        QueryMsg::GetUser { user, strict } => {
+            to_json_binary(&get_user(deps, user, strict.unwrap_or_default())?)
+        }
+        QueryMsg::GetVotingPower { user, strict } => {
+            to_json_binary(&get_voting_power(deps, user, strict.unwrap_or_default())?)
+        }
+        QueryMsg::EarlyUnstakePenalty { user, amount } => {
+            to_json_binary(&get_early_unstake_penalty(deps, env, user, amount)?)
+        }
+        QueryMsg::Position { user } => to_json_binary(&get_position(deps, env, user)?),
+        QueryMsg::VestingInfo { user } => to_json_binary(&get_vesting_info(deps, env, user)?),
+        QueryMsg::LockTiers {} => to_json_binary(&get_lock_tiers(deps)?),
+        QueryMsg::Capacity { user } => to_json_binary(&get_capacity(deps, user)?),
+        QueryMsg::Allowlist { start_after, limit } => {
+            to_json_binary(&list_addresses(deps, ALLOWLIST, start_after, limit)?)
+        }
+        QueryMsg::Frozen { start_after, limit } => {
+            to_json_binary(&list_addresses(deps, FROZEN, start_after, limit)?)
+        }
+        QueryMsg::ReceiptToken {} => to_json_binary(&ReceiptTokenResponse {
+            address: RECEIPT_TOKEN.may_load(deps.storage)?,
+        }),
+        QueryMsg::WithdrawalWindow {} => to_json_binary(&get_withdrawal_window(deps, env)?),
+        #[cfg(feature = "invariants")]
+        QueryMsg::CheckInvariants {} => to_json_binary(&check_invariants(deps, env)?),
     }
 }
 
+/// Loads a user's position with pending penalties credited. Users without a
+/// position get zero values, or a `NotFound` error if `strict` is set.
+fn query_user(deps: Deps, user: &str, strict: bool) -> StdResult<UserInfo> {
+    let user_addr = deps
+        .api
+        .addr_validate(user)
+        .map_err(|err| StdError::generic_err(format!("Invalid user address {user:?}: {err}")))?;
+
+    let mut info = match VOTING_POWER.may_load(deps.storage, &user_addr)? {
+        Some(info) => info,
+        None if strict => return Err(StdError::not_found(format!("Gungnir user {user}"))),
+        None => UserInfo::default(),
+    };
+    info.total_tokens += pending_penalties(deps.storage, &user_addr, &info)?;
+
+    Ok(info)
+}
+
 /// Returns user information from a specified user address
-pub fn get_user(deps: Deps, user: String) -> StdResult<UserInfo> {
-    let user_addr = deps.api.addr_validate(&user).unwrap();
-    Ok(VOTING_POWER.load(deps.storage, &user_addr).unwrap())
+pub fn get_user(deps: Deps, user: String, strict: bool) -> StdResult<UserInfo> {
+    query_user(deps, &user, strict)
 }
 
 /// Returns voting power for a specified user address
-pub fn get_voting_power(deps: Deps, user: String) -> StdResult<u128> {
-    let user_addr = deps.api.addr_validate(&user).unwrap();
-    Ok(VOTING_POWER
-        .load(deps.storage, &user_addr)
-        .unwrap()
-        .voting_power)
+pub fn get_voting_power(deps: Deps, user: String, strict: bool) -> StdResult<u128> {
+    Ok(query_user(deps, &user, strict)?.voting_power)
+}
+
+/// Returns the penalty a user would pay to unstake `amount` early right now
+pub fn get_early_unstake_penalty(
+    deps: Deps,
+    env: Env,
+    user: String,
+    amount: u128,
+) -> StdResult<EarlyUnstakePenaltyResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let user = query_user(deps, &user, false)?;
+    if user.locked_tokens.u128() < amount {
+        return Err(StdError::generic_err(
+            ContractError::InsufficientVotingPower {}.to_string(),
+        ));
+    }
+
+    let penalty = match config.treasury {
+        None if TOTAL_VOTING_POWER.load(deps.storage)? == user.voting_power => Uint128::zero(),
+        _ => early_unstake_penalty(&config, &user, env.block.time, amount),
+    };
+
+    Ok(EarlyUnstakePenaltyResponse {
+        penalty,
+        remaining_lock: user.seconds_until_unlock(env.block.time),
+    })
+}
+
+/// Returns what a user can currently do with their position
+pub fn get_position(deps: Deps, env: Env, user: String) -> StdResult<PositionResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let user_addr = deps.api.addr_validate(&user)?;
+    let unvested = unvested_tokens(deps.storage, &user_addr, env.block.time)?;
+    let user = query_user(deps, &user, false)?;
+    let blocked = config.paused || FROZEN.has(deps.storage, &user_addr);
+
+    let mut withdrawable = if blocked {
+        Uint128::zero()
+    } else {
+        user.withdrawable(unvested, config.stake_unvested)
+    };
+    if let Some(limit) = &config.withdrawal_limit {
+        let used = window_withdrawals(deps.storage, limit, env.block.time)?;
+        withdrawable = withdrawable.min(window_remaining(deps.storage, limit, used)?);
+    }
+
+    Ok(PositionResponse {
+        total_tokens: user.total_tokens,
+        voting_power: user.voting_power,
+        withdrawable,
+        locked: user.locked(),
+        unvested,
+        released_time: user.released_time,
+        seconds_until_unlock: user.seconds_until_unlock(env.block.time),
+        can_unstake: !blocked && !user.locked_tokens.is_zero() && user.is_unlocked(env.block.time),
+    })
+}
+
+/// Returns the vesting schedule of a user, if any
+pub fn get_vesting_info(
+    deps: Deps,
+    env: Env,
+    user: String,
+) -> StdResult<Option<VestingInfoResponse>> {
+    let user_addr = deps.api.addr_validate(&user)?;
+    Ok(VESTING
+        .may_load(deps.storage, &user_addr)?
+        .map(|vesting| VestingInfoResponse {
+            amount: vesting.amount,
+            vested: vesting.schedule.vested(vesting.amount, env.block.time),
+            unvested: vesting.unvested(env.block.time),
+            schedule: vesting.schedule,
+        }))
+}
+
+/// Returns the locks users can choose when staking
+pub fn get_lock_tiers(deps: Deps) -> StdResult<LockTiersResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(LockTiersResponse {
+        lock_period: config.lock_period,
+        tiers: config.lock_tiers,
+        min_stake: config.min_stake,
+    })
+}
+
+/// Returns how much more the contract, and optionally a user, can take in deposits
+pub fn get_capacity(deps: Deps, user: Option<String>) -> StdResult<CapacityResponse> {
+    let user_info = match &user {
+        Some(user) => query_user(deps, user, false)?,
+        None => UserInfo::default(),
+    };
+    let [global, per_user] =
+        remaining_capacity(deps.storage, &user_info, TOTAL_TOKENS.load(deps.storage)?)?;
+
+    Ok(CapacityResponse {
+        global,
+        user: per_user.filter(|_| user.is_some()),
+    })
+}
+
+/// Returns the withdrawal limit with how much of it the current window has used
+pub fn get_withdrawal_window(deps: Deps, env: Env) -> StdResult<WithdrawalWindowResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let Some(limit) = config.withdrawal_limit else {
+        return Ok(WithdrawalWindowResponse {
+            limit: None,
+            used: Uint128::zero(),
+            remaining: None,
+        });
+    };
+
+    let used = window_withdrawals(deps.storage, &limit, env.block.time)?;
+    let remaining = window_remaining(deps.storage, &limit, used)?;
+    Ok(WithdrawalWindowResponse {
+        used,
+        remaining: Some(remaining),
+        limit: Some(limit),
+    })
+}
+
+#[cfg(feature = "invariants")]
+pub fn check_invariants(deps: Deps, env: Env) -> StdResult<crate::msg::InvariantsResponse> {
+    let balance = deps.querier.query_balance(env.contract.address, DENOM)?;
+    let violations = crate::invariants::check(deps.storage, balance.amount)?;
+    Ok(crate::msg::InvariantsResponse {
+        violations: violations.iter().map(ToString::to_string).collect(),
+    })
+}
+
+/// Returns a page of addresses from an address set, in ascending order
+pub fn list_addresses(
+    deps: Deps,
+    set: Map<&Addr, Empty>,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<AddressesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+
+    let addresses = set
+        .keys(
+            deps.storage,
+            start_after.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    Ok(AddressesResponse { addresses })
 }
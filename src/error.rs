@@ -1,4 +1,5 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{CheckedFromRatioError, StdError, Uint128};
+use cw_utils::{ParseReplyError, PaymentError};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,6 +7,15 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+
+    #[error("{0}")]
+    ParseReply(#[from] ParseReplyError),
+
+    #[error("{0}")]
+    CheckedFromRatio(#[from] CheckedFromRatioError),
+
     #[error("Unauthorized")]
     Unauthorized {},
 
@@ -14,4 +24,71 @@ pub enum ContractError {
 
     #[error("Insufficient voting power")]
     InsufficientVotingPower {},
+
+    #[error("Penalty cannot exceed 10000 basis points")]
+    InvalidPenalty {},
+
+    #[error("Contract is paused")]
+    Paused {},
+
+    #[error("Vesting cliff cannot exceed its duration")]
+    InvalidVestingSchedule {},
+
+    #[error("User already has tokens vesting")]
+    VestingExists {},
+
+    #[error("{address} is not on the allowlist")]
+    NotAllowlisted { address: String },
+
+    #[error("{address} is frozen")]
+    Frozen { address: String },
+
+    #[error("Deposit cap exceeded, {headroom} can still be deposited")]
+    CapExceeded { headroom: Uint128 },
+
+    #[error("Stake must lock at least {min_stake} tokens")]
+    StakeTooSmall { min_stake: Uint128 },
+
+    #[error("Unknown lock tier {tier}")]
+    UnknownLockTier { tier: u32 },
+
+    #[error("Lock tiers need a duration and a multiplier of at least 1")]
+    InvalidLockTier {},
+
+    #[error("Deposits add up to {expected} but {received} was attached")]
+    FundsMismatch {
+        expected: Uint128,
+        received: Uint128,
+    },
+
+    #[error("Withdrawal limit of {max_bps} basis points per {window} seconds exceeded, {remaining} can still be withdrawn")]
+    WithdrawalLimitExceeded {
+        max_bps: u64,
+        window: u64,
+        remaining: Uint128,
+    },
+
+    #[error("Withdrawal limits need a window and at most 10000 basis points")]
+    InvalidWithdrawalLimit {},
+
+    #[error("Batch op {index} failed: {error}")]
+    BatchOpFailed {
+        index: usize,
+        error: Box<ContractError>,
+    },
+
+    #[error("Withdrawals require returning receipt tokens")]
+    ReceiptsRequired {},
+
+    #[error("Early unstaking requires returning {penalty} receipt tokens for the penalty")]
+    PenaltyReceiptsRequired { penalty: Uint128 },
+
+    #[error("Only {available} tokens of {owner}'s position are not backed by their receipts")]
+    PositionBacked { owner: String, available: Uint128 },
+
+    #[error("IBC channels must be unordered")]
+    OrderedChannel {},
+
+    #[error("Invalid IBC version {version}, expected {expected}")]
+    InvalidIbcVersion { version: String, expected: String },
 }
@@ -0,0 +1,655 @@
+use std::fmt;
+use std::str::FromStr;
+
+use cosmwasm_std::{Addr, Attribute, Decimal, Event, StdError, StdResult, Timestamp, Uint128};
+
+use crate::state::{LockTier, UserInfo, WithdrawalLimit};
+
+/// Prefix of every event type. Wasm modules emit them as `wasm-gungnir_*`.
+pub const EVENT_PREFIX: &str = "gungnir_";
+
+/// Handler that changed a position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionAction {
+    Deposit,
+    DepositFor,
+    VestingDeposit,
+    Withdraw,
+    Stake,
+    Unstake,
+    EarlyUnstake,
+    ForceUnlock,
+    ForceReturn,
+}
+
+impl PositionAction {
+    pub const ALL: [PositionAction; 9] = [
+        PositionAction::Deposit,
+        PositionAction::DepositFor,
+        PositionAction::VestingDeposit,
+        PositionAction::Withdraw,
+        PositionAction::Stake,
+        PositionAction::Unstake,
+        PositionAction::EarlyUnstake,
+        PositionAction::ForceUnlock,
+        PositionAction::ForceReturn,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PositionAction::Deposit => "deposit",
+            PositionAction::DepositFor => "deposit_for",
+            PositionAction::VestingDeposit => "vesting_deposit",
+            PositionAction::Withdraw => "withdraw",
+            PositionAction::Stake => "stake",
+            PositionAction::Unstake => "unstake",
+            PositionAction::EarlyUnstake => "early_unstake",
+            PositionAction::ForceUnlock => "force_unlock",
+            PositionAction::ForceReturn => "force_return",
+        }
+    }
+
+    /// Event type emitted by the contract for this action
+    pub fn event_type(&self) -> String {
+        format!("{EVENT_PREFIX}{}", self.as_str())
+    }
+}
+
+impl fmt::Display for PositionAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PositionAction {
+    type Err = StdError;
+
+    fn from_str(s: &str) -> StdResult<Self> {
+        PositionAction::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| StdError::parse_err("PositionAction", format!("unknown action {s}")))
+    }
+}
+
+/// The parts of a position reported in events
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PositionSnapshot {
+    pub total_tokens: Uint128,
+    pub voting_power: u128,
+    pub locked_tokens: Uint128,
+    pub released_time: Timestamp,
+}
+
+impl From<&UserInfo> for PositionSnapshot {
+    fn from(user: &UserInfo) -> Self {
+        PositionSnapshot {
+            total_tokens: user.total_tokens,
+            voting_power: user.voting_power,
+            locked_tokens: user.locked_tokens,
+            released_time: user.released_time,
+        }
+    }
+}
+
+/// A change to a user's position. Every handler touching a position emits one,
+/// with the same attributes regardless of the action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PositionEvent {
+    pub action: PositionAction,
+    /// Address that triggered the change, the contract itself for sudo
+    pub sender: Addr,
+    /// Owner of the position
+    pub user: Addr,
+    /// Tokens deposited, withdrawn, locked or unlocked
+    pub amount: Uint128,
+    pub before: PositionSnapshot,
+    pub after: PositionSnapshot,
+}
+
+impl PositionEvent {
+    pub fn new(
+        action: PositionAction,
+        sender: &Addr,
+        user: &Addr,
+        amount: Uint128,
+        before: &UserInfo,
+        after: &UserInfo,
+    ) -> Self {
+        PositionEvent {
+            action,
+            sender: sender.clone(),
+            user: user.clone(),
+            amount,
+            before: before.into(),
+            after: after.into(),
+        }
+    }
+
+    pub fn to_event(&self) -> Event {
+        let snapshot_attributes = |suffix: &str, snapshot: &PositionSnapshot| {
+            [
+                (
+                    format!("total_tokens_{suffix}"),
+                    snapshot.total_tokens.to_string(),
+                ),
+                (
+                    format!("voting_power_{suffix}"),
+                    snapshot.voting_power.to_string(),
+                ),
+                (
+                    format!("locked_tokens_{suffix}"),
+                    snapshot.locked_tokens.to_string(),
+                ),
+                (
+                    format!("released_time_{suffix}"),
+                    snapshot.released_time.nanos().to_string(),
+                ),
+            ]
+        };
+
+        Event::new(self.action.event_type())
+            .add_attribute("sender", &self.sender)
+            .add_attribute("user", &self.user)
+            .add_attribute("amount", self.amount)
+            .add_attributes(snapshot_attributes("before", &self.before))
+            .add_attributes(snapshot_attributes("after", &self.after))
+    }
+
+    /// Parses an event as emitted by the contract or as seen on chain, with the `wasm-` prefix
+    pub fn from_event(event: &Event) -> StdResult<Self> {
+        let action = event_action(&event.ty).ok_or_else(|| {
+            StdError::parse_err(
+                "PositionEvent",
+                format!("not a position event: {}", event.ty),
+            )
+        })?;
+        let snapshot = |suffix: &str| -> StdResult<PositionSnapshot> {
+            Ok(PositionSnapshot {
+                total_tokens: parse_attribute(event, &format!("total_tokens_{suffix}"))?,
+                voting_power: parse_attribute(event, &format!("voting_power_{suffix}"))?,
+                locked_tokens: parse_attribute(event, &format!("locked_tokens_{suffix}"))?,
+                released_time: Timestamp::from_nanos(parse_attribute(
+                    event,
+                    &format!("released_time_{suffix}"),
+                )?),
+            })
+        };
+
+        Ok(PositionEvent {
+            action,
+            sender: Addr::unchecked(attribute(event, "sender")?),
+            user: Addr::unchecked(attribute(event, "user")?),
+            amount: parse_attribute(event, "amount")?,
+            before: snapshot("before")?,
+            after: snapshot("after")?,
+        })
+    }
+
+    /// Parses every position event among `events`, skipping unrelated ones
+    pub fn parse_all(events: &[Event]) -> StdResult<Vec<Self>> {
+        events
+            .iter()
+            .filter(|event| event_action(&event.ty).is_some())
+            .map(PositionEvent::from_event)
+            .collect()
+    }
+}
+
+/// Early unstake penalties credited to a position when it is next touched. The
+/// position event that follows includes the share in its change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PenaltyShareEvent {
+    pub user: Addr,
+    pub amount: Uint128,
+}
+
+impl PenaltyShareEvent {
+    pub const EVENT_TYPE: &'static str = "gungnir_penalty_share";
+
+    pub fn to_event(&self) -> Event {
+        Event::new(Self::EVENT_TYPE)
+            .add_attribute("user", &self.user)
+            .add_attribute("amount", self.amount)
+    }
+
+    /// Parses an event as emitted by the contract or as seen on chain, with the `wasm-` prefix
+    pub fn from_event(event: &Event) -> StdResult<Self> {
+        if event.ty.trim_start_matches("wasm-") != Self::EVENT_TYPE {
+            return Err(StdError::parse_err(
+                "PenaltyShareEvent",
+                format!("not a penalty share event: {}", event.ty),
+            ));
+        }
+        Ok(PenaltyShareEvent {
+            user: Addr::unchecked(attribute(event, "user")?),
+            amount: parse_attribute(event, "amount")?,
+        })
+    }
+
+    /// Parses every penalty share event among `events`, skipping unrelated ones
+    pub fn parse_all(events: &[Event]) -> StdResult<Vec<Self>> {
+        events
+            .iter()
+            .filter(|event| event.ty.trim_start_matches("wasm-") == Self::EVENT_TYPE)
+            .map(PenaltyShareEvent::from_event)
+            .collect()
+    }
+}
+
+/// Configuration change made by the owner
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdminChange {
+    SetAllowlistEnabled {
+        enabled: bool,
+    },
+    UpdateAllowlist {
+        added: Vec<Addr>,
+        removed: Vec<Addr>,
+    },
+    UpdateLockTiers {
+        tiers: Vec<LockTier>,
+        min_stake: Uint128,
+    },
+    UpdateCaps {
+        per_user_cap: Option<Uint128>,
+        global_cap: Option<Uint128>,
+    },
+    UpdateWithdrawalLimit {
+        limit: Option<WithdrawalLimit>,
+    },
+    Freeze {
+        user: Addr,
+    },
+    Unfreeze {
+        user: Addr,
+    },
+}
+
+impl AdminChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminChange::SetAllowlistEnabled { .. } => "set_allowlist_enabled",
+            AdminChange::UpdateAllowlist { .. } => "update_allowlist",
+            AdminChange::UpdateLockTiers { .. } => "update_lock_tiers",
+            AdminChange::UpdateCaps { .. } => "update_caps",
+            AdminChange::UpdateWithdrawalLimit { .. } => "update_withdrawal_limit",
+            AdminChange::Freeze { .. } => "freeze",
+            AdminChange::Unfreeze { .. } => "unfreeze",
+        }
+    }
+}
+
+/// A change to the configuration, the allowlist or the frozen users. Every variant
+/// is emitted as its own event type, e.g. `gungnir_update_caps`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdminEvent {
+    /// Owner that made the change
+    pub sender: Addr,
+    pub change: AdminChange,
+}
+
+impl AdminEvent {
+    /// Names of the admin changes, as they appear in event types
+    pub const ACTIONS: [&'static str; 7] = [
+        "set_allowlist_enabled",
+        "update_allowlist",
+        "update_lock_tiers",
+        "update_caps",
+        "update_withdrawal_limit",
+        "freeze",
+        "unfreeze",
+    ];
+
+    pub fn new(sender: &Addr, change: AdminChange) -> Self {
+        AdminEvent {
+            sender: sender.clone(),
+            change,
+        }
+    }
+
+    pub fn to_event(&self) -> Event {
+        let event = Event::new(format!("{EVENT_PREFIX}{}", self.change.as_str()))
+            .add_attribute("sender", &self.sender);
+        match &self.change {
+            AdminChange::SetAllowlistEnabled { enabled } => {
+                event.add_attribute("enabled", enabled.to_string())
+            }
+            AdminChange::UpdateAllowlist { added, removed } => {
+                let event = add_list(event, "added", join(added));
+                add_list(event, "removed", join(removed))
+            }
+            AdminChange::UpdateLockTiers { tiers, min_stake } => {
+                let tiers: Vec<_> = tiers
+                    .iter()
+                    .map(|tier| format!("{}:{}", tier.duration, tier.multiplier))
+                    .collect();
+                add_list(event, "tiers", tiers.join(","))
+                    .add_attribute("min_stake", min_stake.to_string())
+            }
+            AdminChange::UpdateCaps {
+                per_user_cap,
+                global_cap,
+            } => event
+                .add_attribute("per_user_cap", optional(per_user_cap))
+                .add_attribute("global_cap", optional(global_cap)),
+            AdminChange::UpdateWithdrawalLimit { limit } => event
+                .add_attribute(
+                    "max_bps",
+                    optional(&limit.as_ref().map(|limit| limit.max_bps)),
+                )
+                .add_attribute(
+                    "window",
+                    optional(&limit.as_ref().map(|limit| limit.window)),
+                ),
+            AdminChange::Freeze { user } | AdminChange::Unfreeze { user } => {
+                event.add_attribute("user", user)
+            }
+        }
+    }
+
+    /// Parses an event as emitted by the contract or as seen on chain, with the `wasm-` prefix
+    pub fn from_event(event: &Event) -> StdResult<Self> {
+        let action = admin_action(&event.ty).ok_or_else(|| {
+            StdError::parse_err("AdminEvent", format!("not an admin event: {}", event.ty))
+        })?;
+        let change = match action {
+            "set_allowlist_enabled" => AdminChange::SetAllowlistEnabled {
+                enabled: parse_attribute(event, "enabled")?,
+            },
+            "update_allowlist" => AdminChange::UpdateAllowlist {
+                added: split(list_attribute(event, "added")),
+                removed: split(list_attribute(event, "removed")),
+            },
+            "update_lock_tiers" => AdminChange::UpdateLockTiers {
+                tiers: parse_tiers(list_attribute(event, "tiers"))?,
+                min_stake: parse_attribute(event, "min_stake")?,
+            },
+            "update_caps" => AdminChange::UpdateCaps {
+                per_user_cap: parse_optional(event, "per_user_cap")?,
+                global_cap: parse_optional(event, "global_cap")?,
+            },
+            "update_withdrawal_limit" => {
+                let max_bps = parse_optional(event, "max_bps")?;
+                let window = parse_optional(event, "window")?;
+                AdminChange::UpdateWithdrawalLimit {
+                    limit: max_bps
+                        .zip(window)
+                        .map(|(max_bps, window)| WithdrawalLimit { max_bps, window }),
+                }
+            }
+            "freeze" => AdminChange::Freeze {
+                user: Addr::unchecked(attribute(event, "user")?),
+            },
+            _ => AdminChange::Unfreeze {
+                user: Addr::unchecked(attribute(event, "user")?),
+            },
+        };
+
+        Ok(AdminEvent {
+            sender: Addr::unchecked(attribute(event, "sender")?),
+            change,
+        })
+    }
+
+    /// Parses every admin event among `events`, skipping unrelated ones
+    pub fn parse_all(events: &[Event]) -> StdResult<Vec<Self>> {
+        events
+            .iter()
+            .filter(|event| admin_action(&event.ty).is_some())
+            .map(AdminEvent::from_event)
+            .collect()
+    }
+}
+
+/// Intervention by chain governance, emitted as a `gungnir_sudo` event naming it
+/// in its `action` attribute
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SudoEvent {
+    ForceUnlock {
+        user: Addr,
+    },
+    SetLockPeriod {
+        lock_period: u64,
+    },
+    SetPaused {
+        paused: bool,
+    },
+    /// `amount` is what was sent back, tokens backing receipts staying in the position
+    ForceReturn {
+        user: Addr,
+        amount: Uint128,
+    },
+}
+
+impl SudoEvent {
+    pub const EVENT_TYPE: &'static str = "gungnir_sudo";
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SudoEvent::ForceUnlock { .. } => "force_unlock",
+            SudoEvent::SetLockPeriod { .. } => "set_lock_period",
+            SudoEvent::SetPaused { .. } => "set_paused",
+            SudoEvent::ForceReturn { .. } => "force_return",
+        }
+    }
+
+    pub fn to_event(&self) -> Event {
+        let event = Event::new(Self::EVENT_TYPE).add_attribute("action", self.as_str());
+        match self {
+            SudoEvent::ForceUnlock { user } => event.add_attribute("user", user),
+            SudoEvent::SetLockPeriod { lock_period } => {
+                event.add_attribute("lock_period", lock_period.to_string())
+            }
+            SudoEvent::SetPaused { paused } => event.add_attribute("paused", paused.to_string()),
+            SudoEvent::ForceReturn { user, amount } => event
+                .add_attribute("user", user)
+                .add_attribute("amount", amount.to_string()),
+        }
+    }
+
+    /// Parses an event as emitted by the contract or as seen on chain, with the `wasm-` prefix
+    pub fn from_event(event: &Event) -> StdResult<Self> {
+        if event.ty.trim_start_matches("wasm-") != Self::EVENT_TYPE {
+            return Err(StdError::parse_err(
+                "SudoEvent",
+                format!("not a sudo event: {}", event.ty),
+            ));
+        }
+        match attribute(event, "action")? {
+            "force_unlock" => Ok(SudoEvent::ForceUnlock {
+                user: Addr::unchecked(attribute(event, "user")?),
+            }),
+            "set_lock_period" => Ok(SudoEvent::SetLockPeriod {
+                lock_period: parse_attribute(event, "lock_period")?,
+            }),
+            "set_paused" => Ok(SudoEvent::SetPaused {
+                paused: parse_attribute(event, "paused")?,
+            }),
+            "force_return" => Ok(SudoEvent::ForceReturn {
+                user: Addr::unchecked(attribute(event, "user")?),
+                amount: parse_attribute(event, "amount")?,
+            }),
+            action => Err(StdError::parse_err(
+                "SudoEvent",
+                format!("unknown action {action}"),
+            )),
+        }
+    }
+
+    /// Parses every sudo event among `events`, skipping unrelated ones
+    pub fn parse_all(events: &[Event]) -> StdResult<Vec<Self>> {
+        events
+            .iter()
+            .filter(|event| event.ty.trim_start_matches("wasm-") == Self::EVENT_TYPE)
+            .map(SudoEvent::from_event)
+            .collect()
+    }
+}
+
+/// One op of a batch, carrying the attributes its handler returned. The events the
+/// op emitted follow it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchEvent {
+    /// Position of the op in the batch
+    pub index: usize,
+    /// Attributes of the op, such as its `action`
+    pub attributes: Vec<Attribute>,
+}
+
+impl BatchEvent {
+    pub const EVENT_TYPE: &'static str = "gungnir_batch_op";
+
+    pub fn new(index: usize, attributes: Vec<Attribute>) -> Self {
+        BatchEvent { index, attributes }
+    }
+
+    pub fn to_event(&self) -> Event {
+        Event::new(Self::EVENT_TYPE)
+            .add_attribute("index", self.index.to_string())
+            .add_attributes(self.attributes.clone())
+    }
+
+    /// Parses an event as emitted by the contract or as seen on chain, with the `wasm-`
+    /// prefix and the attributes the chain reserves, like `_contract_address`, left out
+    pub fn from_event(event: &Event) -> StdResult<Self> {
+        if event.ty.trim_start_matches("wasm-") != Self::EVENT_TYPE {
+            return Err(StdError::parse_err(
+                "BatchEvent",
+                format!("not a batch event: {}", event.ty),
+            ));
+        }
+        Ok(BatchEvent {
+            index: parse_attribute(event, "index")?,
+            attributes: event
+                .attributes
+                .iter()
+                .filter(|attr| attr.key != "index" && !attr.key.starts_with('_'))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Parses every batch event among `events`, skipping unrelated ones
+    pub fn parse_all(events: &[Event]) -> StdResult<Vec<Self>> {
+        events
+            .iter()
+            .filter(|event| event.ty.trim_start_matches("wasm-") == Self::EVENT_TYPE)
+            .map(BatchEvent::from_event)
+            .collect()
+    }
+
+    /// Value the op returned for `key`, if any
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attr| attr.key == key)
+            .map(|attr| attr.value.as_str())
+    }
+}
+
+/// Action of a position event type, with or without the `wasm-` prefix
+fn event_action(ty: &str) -> Option<PositionAction> {
+    ty.trim_start_matches("wasm-")
+        .strip_prefix(EVENT_PREFIX)?
+        .parse()
+        .ok()
+}
+
+/// Action of an admin event type, with or without the `wasm-` prefix
+fn admin_action(ty: &str) -> Option<&'static str> {
+    let action = ty.trim_start_matches("wasm-").strip_prefix(EVENT_PREFIX)?;
+    AdminEvent::ACTIONS
+        .into_iter()
+        .find(|candidate| *candidate == action)
+}
+
+fn attribute<'a>(event: &'a Event, key: &str) -> StdResult<&'a str> {
+    event
+        .attributes
+        .iter()
+        .find(|attr| attr.key == key)
+        .map(|attr| attr.value.as_str())
+        .ok_or_else(|| StdError::parse_err(&event.ty, format!("missing attribute {key}")))
+}
+
+fn parse_attribute<T>(event: &Event, key: &str) -> StdResult<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    parse_value(event, key, attribute(event, key)?)
+}
+
+fn parse_value<T>(event: &Event, key: &str, value: &str) -> StdResult<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| StdError::parse_err(&event.ty, format!("invalid attribute {key}: {err}")))
+}
+
+/// Attribute value of an optional setting, `none` if unset
+fn optional<T: fmt::Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "none".to_string(), T::to_string)
+}
+
+fn parse_optional<T>(event: &Event, key: &str) -> StdResult<Option<T>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match attribute(event, key)? {
+        "none" => Ok(None),
+        value => parse_value(event, key, value).map(Some),
+    }
+}
+
+/// Adds a comma separated list, left out when empty as attribute values cannot be empty
+fn add_list(event: Event, key: &str, list: String) -> Event {
+    if list.is_empty() {
+        event
+    } else {
+        event.add_attribute(key, list)
+    }
+}
+
+fn list_attribute<'a>(event: &'a Event, key: &str) -> &'a str {
+    attribute(event, key).unwrap_or_default()
+}
+
+fn join(addresses: &[Addr]) -> String {
+    addresses
+        .iter()
+        .map(Addr::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split(addresses: &str) -> Vec<Addr> {
+    addresses
+        .split(',')
+        .filter(|addr| !addr.is_empty())
+        .map(Addr::unchecked)
+        .collect()
+}
+
+/// Lock tiers written as comma separated `duration:multiplier` pairs
+fn parse_tiers(tiers: &str) -> StdResult<Vec<LockTier>> {
+    tiers
+        .split(',')
+        .filter(|tier| !tier.is_empty())
+        .map(|tier| {
+            let invalid = || StdError::parse_err("LockTier", format!("invalid tier {tier}"));
+            let (duration, multiplier) = tier.split_once(':').ok_or_else(invalid)?;
+            Ok(LockTier {
+                duration: duration.parse().map_err(|_| invalid())?,
+                multiplier: Decimal::from_str(multiplier).map_err(|_| invalid())?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,277 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    from_json, to_json_binary, Addr, Binary, Deps, DepsMut, Empty, Env, IbcBasicResponse,
+    IbcChannel, IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcMsg, IbcOrder,
+    IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, IbcTimeout,
+    Order, StdAck, StdResult, Storage,
+};
+
+use crate::error::ContractError;
+use crate::msg::{IbcPacketMsg, IbcUpdate, IbcUpdatePacket, TotalsPacket, VotingPowerPacket};
+use crate::state::{
+    IBC_CHANNELS, IBC_SUBSCRIPTIONS, TOTAL_TOKENS, TOTAL_VOTING_POWER, VOTING_POWER,
+};
+
+pub const IBC_VERSION: &str = "gungnir-vp-1";
+/// Seconds before an update packet times out and is sent again
+pub const IBC_PACKET_LIFETIME: u64 = 60 * 60; // One hour
+/// Times a timed out update is sent again before it is dropped
+pub const IBC_MAX_RETRIES: u32 = 3;
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_open(
+    _deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelOpenMsg,
+) -> Result<(), ContractError> {
+    validate_channel(msg.channel(), msg.counterparty_version())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_connect(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelConnectMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel = msg.channel();
+    validate_channel(channel, msg.counterparty_version())?;
+
+    IBC_CHANNELS.save(deps.storage, &channel.endpoint.channel_id, &Empty {})?;
+
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_channel_connect")
+        .add_attribute("channel_id", &channel.endpoint.channel_id))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_close(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelCloseMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel_id = msg.channel().endpoint.channel_id.as_str();
+
+    IBC_CHANNELS.remove(deps.storage, channel_id);
+    let subscribers = IBC_SUBSCRIPTIONS
+        .prefix(channel_id)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for user in &subscribers {
+        IBC_SUBSCRIPTIONS.remove(deps.storage, (channel_id, user));
+    }
+
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_channel_close")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("subscriptions", subscribers.len().to_string()))
+}
+
+/// Answers a counterparty packet. Failures are returned as error acknowledgements
+/// rather than aborting the transaction, so the relayer can deliver them.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_receive(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let channel_id = msg.packet.dest.channel_id.as_str();
+    let res = from_json(&msg.packet.data)
+        .map_err(ContractError::from)
+        .and_then(|packet| receive_packet(deps, channel_id, packet));
+
+    Ok(match res {
+        Ok((action, data)) => IbcReceiveResponse::new()
+            .set_ack(StdAck::success(data))
+            .add_attribute("action", action)
+            .add_attribute("channel_id", channel_id),
+        Err(err) => IbcReceiveResponse::new()
+            .set_ack(StdAck::error(err.to_string()))
+            .add_attribute("action", "ibc_packet_receive")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("error", err.to_string()),
+    })
+}
+
+fn receive_packet(
+    deps: DepsMut,
+    channel_id: &str,
+    packet: IbcPacketMsg,
+) -> Result<(&'static str, Binary), ContractError> {
+    match packet {
+        IbcPacketMsg::VotingPower { user } => {
+            let user = deps.api.addr_validate(&user)?;
+            let data = to_json_binary(&voting_power_packet(deps.storage, &user)?)?;
+            Ok(("ibc_voting_power", data))
+        }
+        IbcPacketMsg::Totals {} => {
+            let data = to_json_binary(&totals_packet(deps.storage)?)?;
+            Ok(("ibc_totals", data))
+        }
+        IbcPacketMsg::Subscribe { user } => {
+            let user = deps.api.addr_validate(&user)?;
+            IBC_SUBSCRIPTIONS.save(deps.storage, (channel_id, &user), &Empty {})?;
+            let data = to_json_binary(&voting_power_packet(deps.storage, &user)?)?;
+            Ok(("ibc_subscribe", data))
+        }
+        IbcPacketMsg::Unsubscribe { user } => {
+            let user = deps.api.addr_validate(&user)?;
+            IBC_SUBSCRIPTIONS.remove(deps.storage, (channel_id, &user));
+            Ok(("ibc_unsubscribe", Binary::default()))
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_ack(
+    _deps: DepsMut,
+    _env: Env,
+    msg: IbcPacketAckMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let packet: IbcUpdatePacket = from_json(&msg.original_packet.data)?;
+    let ack: StdAck = from_json(&msg.acknowledgement.data)?;
+
+    let response = update_attributes(
+        IbcBasicResponse::new()
+            .add_attribute("action", "ibc_packet_ack")
+            .add_attribute("channel_id", &msg.original_packet.src.channel_id),
+        &packet.update,
+    );
+    Ok(match ack {
+        StdAck::Success(_) => response.add_attribute("success", "true"),
+        StdAck::Error(err) => response
+            .add_attribute("success", "false")
+            .add_attribute("error", err),
+    })
+}
+
+/// Sends the timed out update again with the current values, unless the channel or the
+/// subscriptions are gone or the update already timed out `IBC_MAX_RETRIES` times
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_timeout(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let packet: IbcUpdatePacket = from_json(&msg.packet.data)?;
+    let channel_id = msg.packet.src.channel_id.as_str();
+
+    let response = update_attributes(
+        IbcBasicResponse::new()
+            .add_attribute("action", "ibc_packet_timeout")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("retries", packet.retries.to_string()),
+        &packet.update,
+    );
+    if packet.retries >= IBC_MAX_RETRIES || !IBC_CHANNELS.has(deps.storage, channel_id) {
+        return Ok(response.add_attribute("retried", "false"));
+    }
+
+    let update = match &packet.update {
+        IbcUpdate::VotingPower(update) => {
+            let user = Addr::unchecked(&update.user);
+            IBC_SUBSCRIPTIONS
+                .has(deps.storage, (channel_id, &user))
+                .then(|| voting_power_packet(deps.storage, &user).map(IbcUpdate::VotingPower))
+        }
+        IbcUpdate::Totals(_) => has_subscriptions(deps.storage, channel_id)
+            .then(|| totals_packet(deps.storage).map(IbcUpdate::Totals)),
+    };
+    let Some(update) = update.transpose()? else {
+        return Ok(response.add_attribute("retried", "false"));
+    };
+
+    let retry = update_packet(channel_id, update, packet.retries + 1, &env)?;
+    Ok(response.add_attribute("retried", "true").add_message(retry))
+}
+
+/// Update packets after a change to `user`'s voting power: their voting power for the
+/// channels subscribed to them, the new totals for the other channels with subscriptions
+pub fn voting_power_updates(deps: Deps, env: &Env, user: &Addr) -> StdResult<Vec<IbcMsg>> {
+    let channels = IBC_CHANNELS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut updates = vec![];
+    for channel_id in channels {
+        let update = if IBC_SUBSCRIPTIONS.has(deps.storage, (&channel_id, user)) {
+            IbcUpdate::VotingPower(voting_power_packet(deps.storage, user)?)
+        } else if has_subscriptions(deps.storage, &channel_id) {
+            IbcUpdate::Totals(totals_packet(deps.storage)?)
+        } else {
+            continue;
+        };
+        updates.push(update_packet(&channel_id, update, 0, env)?);
+    }
+    Ok(updates)
+}
+
+fn has_subscriptions(storage: &dyn Storage, channel_id: &str) -> bool {
+    IBC_SUBSCRIPTIONS
+        .prefix(channel_id)
+        .keys_raw(storage, None, None, Order::Ascending)
+        .next()
+        .is_some()
+}
+
+fn update_attributes(response: IbcBasicResponse, update: &IbcUpdate) -> IbcBasicResponse {
+    match update {
+        IbcUpdate::VotingPower(update) => response
+            .add_attribute("update", "voting_power")
+            .add_attribute("user", &update.user),
+        IbcUpdate::Totals(_) => response.add_attribute("update", "totals"),
+    }
+}
+
+fn voting_power_packet(storage: &dyn Storage, user: &Addr) -> StdResult<VotingPowerPacket> {
+    let voting_power = VOTING_POWER
+        .may_load(storage, user)?
+        .map(|user| user.voting_power)
+        .unwrap_or_default();
+    Ok(VotingPowerPacket {
+        user: user.to_string(),
+        voting_power,
+        total_voting_power: TOTAL_VOTING_POWER.load(storage)?,
+    })
+}
+
+fn totals_packet(storage: &dyn Storage) -> StdResult<TotalsPacket> {
+    Ok(TotalsPacket {
+        total_tokens: TOTAL_TOKENS.load(storage)?,
+        total_voting_power: TOTAL_VOTING_POWER.load(storage)?,
+    })
+}
+
+fn update_packet(
+    channel_id: &str,
+    update: IbcUpdate,
+    retries: u32,
+    env: &Env,
+) -> StdResult<IbcMsg> {
+    Ok(IbcMsg::SendPacket {
+        channel_id: channel_id.to_string(),
+        data: to_json_binary(&IbcUpdatePacket { update, retries })?,
+        timeout: IbcTimeout::with_timestamp(env.block.time.plus_seconds(IBC_PACKET_LIFETIME)),
+    })
+}
+
+fn validate_channel(
+    channel: &IbcChannel,
+    counterparty_version: Option<&str>,
+) -> Result<(), ContractError> {
+    if channel.order != IbcOrder::Unordered {
+        return Err(ContractError::OrderedChannel {});
+    }
+    for version in [Some(channel.version.as_str()), counterparty_version]
+        .into_iter()
+        .flatten()
+    {
+        if version != IBC_VERSION {
+            return Err(ContractError::InvalidIbcVersion {
+                version: version.to_string(),
+                expected: IBC_VERSION.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Safety properties of the contract that hold between any two transactions,
+//! checked against its storage and the balance it holds.
+
+use cosmwasm_std::{Decimal, MemoryStorage, Order, Record, StdResult, Storage, Uint128, Uint256};
+use thiserror::Error;
+
+use crate::contract::{pending_penalties, DENOM};
+use crate::state::{CONFIG, PENALTY_POOL, TOTAL_TOKENS, TOTAL_VOTING_POWER, VOTING_POWER};
+
+/// Broken safety property
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum InvariantViolation {
+    /// The tokens held fall short of the tokens owed to users and the penalty pool
+    #[error("contract holds {balance}{DENOM} but owes {owed}{DENOM}")]
+    Balance { balance: Uint128, owed: Uint128 },
+
+    #[error("total tokens is {stored} but positions and the penalty pool hold {sum}")]
+    TotalTokens { stored: Uint128, sum: Uint128 },
+
+    #[error("total voting power is {stored} but positions hold {sum}")]
+    TotalVotingPower { stored: u128, sum: u128 },
+
+    /// Voting power beyond the tokens of the user, at the largest lock tier multiplier
+    #[error("{user} has {voting_power} voting power for {total_tokens} tokens")]
+    VotingPower {
+        user: String,
+        voting_power: u128,
+        total_tokens: Uint128,
+    },
+
+    #[error("{user} has {locked_tokens} tokens locked out of {total_tokens}")]
+    LockedTokens {
+        user: String,
+        locked_tokens: Uint128,
+        total_tokens: Uint128,
+    },
+
+    /// Settling every pending penalty would take the penalty pool below zero
+    #[error("positions are owed {pending} in penalties but the pool holds {pool}")]
+    PenaltyPool { pending: Uint128, pool: Uint128 },
+}
+
+/// Checks every safety property given the `DENOM` balance of the contract,
+/// returning the broken ones
+pub fn check(storage: &dyn Storage, balance: Uint128) -> StdResult<Vec<InvariantViolation>> {
+    let config = CONFIG.load(storage)?;
+    // boosted tiers grant more voting power than tokens locked
+    let max_multiplier = config
+        .lock_tiers
+        .iter()
+        .map(|tier| tier.multiplier)
+        .fold(Decimal::one(), Decimal::max);
+
+    let mut violations = vec![];
+    // sums are widened so that overflowing positions are reported rather than panicking
+    let mut total_tokens = Uint256::zero();
+    let mut total_voting_power = Uint256::zero();
+    let mut pending = Uint256::zero();
+    for item in VOTING_POWER.range(storage, None, None, Order::Ascending) {
+        let (addr, user) = item?;
+        total_tokens += Uint256::from(user.total_tokens);
+        total_voting_power += Uint256::from(user.voting_power);
+        pending += Uint256::from(pending_penalties(storage, &addr, &user)?);
+
+        let bound = user.total_tokens.checked_mul_ceil(max_multiplier);
+        if bound.is_ok_and(|bound| user.voting_power > bound.u128()) {
+            violations.push(InvariantViolation::VotingPower {
+                user: addr.to_string(),
+                voting_power: user.voting_power,
+                total_tokens: user.total_tokens,
+            });
+        }
+        if user.locked_tokens > user.total_tokens {
+            violations.push(InvariantViolation::LockedTokens {
+                user: addr.to_string(),
+                locked_tokens: user.locked_tokens,
+                total_tokens: user.total_tokens,
+            });
+        }
+    }
+
+    let pool = PENALTY_POOL.may_load(storage)?.unwrap_or_default();
+    if pending > Uint256::from(pool) {
+        violations.push(InvariantViolation::PenaltyPool {
+            pending: narrow(pending),
+            pool,
+        });
+    }
+
+    // tokens sent to the contract outside of a deposit are a surplus owed to nobody
+    let owed = narrow(total_tokens + Uint256::from(pool));
+    if balance < owed {
+        violations.push(InvariantViolation::Balance { balance, owed });
+    }
+    let stored = TOTAL_TOKENS.load(storage)?;
+    if stored != owed {
+        violations.push(InvariantViolation::TotalTokens { stored, sum: owed });
+    }
+    let stored = TOTAL_VOTING_POWER.load(storage)?;
+    let sum = narrow(total_voting_power).u128();
+    if stored != sum {
+        violations.push(InvariantViolation::TotalVotingPower { stored, sum });
+    }
+
+    Ok(violations)
+}
+
+/// Checks the safety properties over a raw dump of the contract storage, such as
+/// the records of a multi-test contract
+pub fn check_records(
+    records: impl IntoIterator<Item = Record>,
+    balance: Uint128,
+) -> StdResult<Vec<InvariantViolation>> {
+    let mut storage = MemoryStorage::new();
+    for (key, value) in records {
+        storage.set(&key, &value);
+    }
+    check(&storage, balance)
+}
+
+/// Saturates sums past the range of the stored values
+fn narrow(value: Uint256) -> Uint128 {
+    Uint128::try_from(value).unwrap_or(Uint128::MAX)
+}
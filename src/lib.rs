@@ -2,7 +2,14 @@ pub mod contract;
 // pub mod contract_model_test;
 // pub mod contract_model_test_generated;
 mod error;
+pub mod events;
+pub mod ibc;
+pub mod invariants;
+#[cfg(feature = "mbt")]
+pub mod mbt;
 // pub mod integration_tests;
+#[cfg(any(test, feature = "mbt"))]
+pub mod model;
 pub mod msg;
 pub mod state;
 
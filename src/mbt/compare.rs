@@ -0,0 +1,320 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use cosmwasm_std::{Addr, MemoryStorage, Order, StdResult, Storage, Timestamp, Uint128};
+use cw_multi_test::{App, AppResponse};
+use num_bigint::BigInt;
+
+use super::trace::{to_u128, BankMsg, Coin, CosmosMsg, State, SubMsg, WasmMsg};
+use super::MbtError;
+use crate::msg::QueryMsg;
+use crate::state::{UserInfo, VOTING_POWER};
+
+/// Values of the model and the contract that differ, shown side by side
+#[derive(Debug)]
+pub struct StateDiff {
+    /// Title of the column the model is compared against
+    against: &'static str,
+    rows: Vec<(String, String, String)>,
+}
+
+impl Default for StateDiff {
+    fn default() -> Self {
+        StateDiff::against("contract")
+    }
+}
+
+impl StateDiff {
+    /// Empty diff comparing the model against `against` instead of the contract
+    pub fn against(against: &'static str) -> Self {
+        StateDiff {
+            against,
+            rows: vec![],
+        }
+    }
+
+    /// Records `field` if the model and contract values differ
+    pub fn check<T: PartialEq + fmt::Display>(&mut self, field: String, model: T, contract: T) {
+        if model != contract {
+            self.rows
+                .push((field, model.to_string(), contract.to_string()));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = |column: fn(&(String, String, String)) -> &String, title: &str| {
+            self.rows
+                .iter()
+                .map(|row| column(row).len())
+                .fold(title.len(), usize::max)
+        };
+        let field_width = width(|row| &row.0, "field");
+        let model_width = width(|row| &row.1, "model");
+
+        write!(
+            f,
+            "  {:field_width$}  {:model_width$}  {}",
+            "field", "model", self.against
+        )?;
+        for (field, model, contract) in &self.rows {
+            write!(
+                f,
+                "\n  {field:field_width$}  {model:model_width$}  {contract}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares every position, of the model or of the contract, and every bank balance
+/// of the model with the contract and the chain. `block_time` maps model times to block times.
+pub fn compare_state(
+    app: &App,
+    contract_addr: &Addr,
+    state: &State,
+    block_time: &dyn Fn(&BigInt) -> Result<Timestamp, MbtError>,
+) -> Result<StateDiff, MbtError> {
+    let mut diff = StateDiff::default();
+
+    // positions the model does not know about are divergences too
+    let mut storage = MemoryStorage::new();
+    for (key, value) in app.dump_wasm_raw(contract_addr) {
+        storage.set(&key, &value);
+    }
+    let contract_users = VOTING_POWER
+        .keys(&storage, None, None, Order::Ascending)
+        .map(|user| user.map(String::from))
+        .collect::<StdResult<BTreeSet<_>>>()
+        .map_err(|err| MbtError::Setup(err.to_string()))?;
+    let users: BTreeSet<_> = state
+        .contract_state
+        .voting_power
+        .keys()
+        .cloned()
+        .chain(contract_users.iter().cloned())
+        .collect();
+
+    for user in users {
+        let Some(expected) = state.contract_state.voting_power.get(&user) else {
+            diff.check(format!("{user}.position"), false, true);
+            continue;
+        };
+        let msg = QueryMsg::GetUser {
+            user: user.clone(),
+            strict: Some(true),
+        };
+        let actual: UserInfo = match app.wrap().query_wasm_smart(contract_addr, &msg) {
+            Ok(actual) => actual,
+            Err(_) if !contract_users.contains(&user) => {
+                diff.check(format!("{user}.position"), true, false);
+                continue;
+            }
+            Err(err) => {
+                diff.check(user.clone(), "position".to_string(), err.to_string());
+                continue;
+            }
+        };
+
+        diff.check(
+            format!("{user}.total_tokens"),
+            Uint128::new(to_u128(&expected.total_tokens, "total_tokens")?),
+            actual.total_tokens,
+        );
+        diff.check(
+            format!("{user}.voting_power"),
+            to_u128(&expected.voting_power, "voting_power")?,
+            actual.voting_power,
+        );
+        diff.check(
+            format!("{user}.locked_tokens"),
+            Uint128::new(to_u128(expected.locked(), "locked_tokens")?),
+            actual.locked_tokens,
+        );
+        // positions that never staked have no release time on either side
+        let released_time = if expected.released_time == BigInt::from(0) {
+            Timestamp::default()
+        } else {
+            block_time(&expected.released_time)?
+        };
+        diff.check(
+            format!("{user}.released_time"),
+            released_time.seconds(),
+            actual.released_time.seconds(),
+        );
+    }
+
+    let mut balances: Vec<_> = state
+        .bank
+        .iter()
+        .flat_map(|(addr, balances)| {
+            balances
+                .iter()
+                .map(move |(denom, amount)| (addr, denom, amount))
+        })
+        .collect();
+    balances.sort();
+    for (addr, denom, amount) in balances {
+        let actual = app
+            .wrap()
+            .query_balance(addr, denom)
+            .map_err(|err| MbtError::Setup(err.to_string()))?;
+        diff.check(
+            format!("bank.{addr}.{denom}"),
+            Uint128::new(to_u128(amount, "bank balance")?),
+            actual.amount,
+        );
+    }
+
+    Ok(diff)
+}
+
+/// Compares the submessages the model emitted for an action with the transfers out of the
+/// contract and the instantiations executed by the chain, in order
+pub fn compare_messages(contract_addr: &Addr, messages: &[SubMsg], res: &AppResponse) -> StateDiff {
+    let executed: Vec<_> = res
+        .events
+        .iter()
+        .filter_map(|event| {
+            let attr = |key: &str| {
+                event
+                    .attributes
+                    .iter()
+                    .find(|attr| attr.key == key)
+                    .map(|attr| attr.value.as_str())
+                    .unwrap_or_default()
+            };
+            match event.ty.as_str() {
+                "transfer" if attr("sender") == contract_addr.as_str() => {
+                    Some(format!("send {} to {}", attr("amount"), attr("recipient")))
+                }
+                "instantiate" => Some(format!("instantiate code {}", attr("code_id"))),
+                _ => None,
+            }
+        })
+        .collect();
+
+    diff_messages(
+        StateDiff::default(),
+        &describe_messages(messages),
+        &executed,
+    )
+}
+
+/// Compares the submessages the model emitted for an action with the ones of the
+/// reference model
+pub fn compare_model_messages(messages: &[SubMsg], reference: &[SubMsg]) -> StateDiff {
+    diff_messages(
+        StateDiff::against("reference"),
+        &describe_messages(messages),
+        &describe_messages(reference),
+    )
+}
+
+/// Compares every position and bank balance of the model with the reference model
+pub fn compare_model_state(state: &State, reference: &State) -> StateDiff {
+    let mut diff = StateDiff::against("reference");
+
+    let users: BTreeSet<_> = state
+        .contract_state
+        .voting_power
+        .keys()
+        .chain(reference.contract_state.voting_power.keys())
+        .collect();
+    for user in users {
+        let positions = [state, reference].map(|state| state.contract_state.voting_power.get(user));
+        let [Some(expected), Some(actual)] = positions else {
+            let exists = positions.map(|position| position.is_some());
+            diff.check(format!("{user}.position"), exists[0], exists[1]);
+            continue;
+        };
+        diff.check(
+            format!("{user}.total_tokens"),
+            &expected.total_tokens,
+            &actual.total_tokens,
+        );
+        diff.check(
+            format!("{user}.voting_power"),
+            &expected.voting_power,
+            &actual.voting_power,
+        );
+        diff.check(
+            format!("{user}.locked_tokens"),
+            expected.locked(),
+            actual.locked(),
+        );
+        diff.check(
+            format!("{user}.released_time"),
+            &expected.released_time,
+            &actual.released_time,
+        );
+    }
+
+    let balance = |state: &State, addr: &str, denom: &str| {
+        state
+            .bank
+            .get(addr)
+            .and_then(|balances| balances.get(denom))
+            .cloned()
+            .unwrap_or_default()
+    };
+    let balances: BTreeSet<_> = [state, reference]
+        .iter()
+        .flat_map(|state| {
+            state.bank.iter().flat_map(|(addr, balances)| {
+                balances
+                    .keys()
+                    .map(move |denom| (addr.as_str(), denom.as_str()))
+            })
+        })
+        .collect();
+    for (addr, denom) in balances {
+        diff.check(
+            format!("bank.{addr}.{denom}"),
+            balance(state, addr, denom),
+            balance(reference, addr, denom),
+        );
+    }
+
+    diff
+}
+
+fn describe_messages(messages: &[SubMsg]) -> Vec<String> {
+    messages
+        .iter()
+        .map(|submsg| match &submsg.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => {
+                format!("send {} to {to_address}", coins_to_string(amount))
+            }
+            CosmosMsg::Wasm(WasmMsg::Instantiate { code_id, .. }) => {
+                format!("instantiate code {code_id}")
+            }
+        })
+        .collect()
+}
+
+fn diff_messages(mut diff: StateDiff, expected: &[String], actual: &[String]) -> StateDiff {
+    for index in 0..expected.len().max(actual.len()) {
+        let none = || "none".to_string();
+        diff.check(
+            format!("messages[{index}]"),
+            expected.get(index).cloned().unwrap_or_else(none),
+            actual.get(index).cloned().unwrap_or_else(none),
+        );
+    }
+    diff
+}
+
+/// Coins in the format of bank transfer events, e.g. `100testcoin,5other`
+fn coins_to_string(coins: &[Coin]) -> String {
+    coins
+        .iter()
+        .map(|coin| format!("{}{}", coin.amount, coin.denom))
+        .collect::<Vec<_>>()
+        .join(",")
+}
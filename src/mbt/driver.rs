@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+
+use cosmwasm_std::{coin, Addr, BankMsg, Coin, Empty, Timestamp};
+use cw_multi_test::{App, AppResponse, BankSudo, Contract, ContractWrapper, Executor, SudoMsg};
+use itf::Trace;
+use num_bigint::BigInt;
+
+use super::compare::{
+    compare_messages, compare_model_messages, compare_model_state, compare_state,
+};
+use super::errors::ErrorTable;
+use super::time::{self, TimeMapping};
+use super::trace::{to_u128, NondetPicks, Response, State, SubMsg};
+use super::MbtError;
+use crate::contract::{self, LOCK_PERIOD};
+use crate::invariants;
+use crate::model::{Model, ModelError};
+use crate::msg::{self, ExecuteMsg, InstantiateMsg};
+
+/// Maps the actions of the model to contract messages
+pub trait ActionMapper {
+    /// Message executed for `action`, `None` if the action is unknown
+    fn execute_msg(
+        &self,
+        action: &str,
+        picks: &NondetPicks,
+    ) -> Option<Result<ExecuteMsg, MbtError>>;
+
+    /// Message instantiating the contract on the init action
+    fn instantiate_msg(&self, _picks: &NondetPicks) -> Result<InstantiateMsg, MbtError> {
+        Ok(InstantiateMsg::default())
+    }
+}
+
+type MsgBuilder = Box<dyn Fn(&NondetPicks) -> Result<ExecuteMsg, MbtError>>;
+
+/// Action mapper built by registering a message builder per action name
+#[derive(Default)]
+pub struct ActionRegistry {
+    actions: HashMap<String, MsgBuilder>,
+}
+
+impl ActionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(
+        mut self,
+        action: &str,
+        build: impl Fn(&NondetPicks) -> Result<ExecuteMsg, MbtError> + 'static,
+    ) -> Self {
+        self.actions.insert(action.to_string(), Box::new(build));
+        self
+    }
+}
+
+impl ActionMapper for ActionRegistry {
+    fn execute_msg(
+        &self,
+        action: &str,
+        picks: &NondetPicks,
+    ) -> Option<Result<ExecuteMsg, MbtError>> {
+        self.actions.get(action).map(|build| build(picks))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReplayConfig {
+    /// Action instantiating the contract
+    pub init_action: String,
+    /// Block time and height per unit of model time
+    pub time: TimeMapping,
+    /// Lock period of the model in units of model time, set on the contract after
+    /// instantiation. The contract keeps its own lock period if unset.
+    pub lock_period: Option<u64>,
+    /// Contract errors expected for the errors of the model. By default every error of
+    /// the model must be mapped to a variant.
+    pub errors: ErrorTable,
+    /// Bank address of the contract in the model
+    pub contract_address: String,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            init_action: "q::init".to_string(),
+            time: TimeMapping::default(),
+            lock_period: None,
+            errors: ErrorTable::gungnir().strict(true),
+            contract_address: "contract0".to_string(),
+        }
+    }
+}
+
+pub fn gungnir_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(contract::execute, contract::instantiate, contract::query)
+        .with_sudo(contract::sudo)
+        .with_reply(contract::reply);
+    Box::new(contract)
+}
+
+/// Replays traces step by step, comparing the outcome of each action with the model
+pub struct Replay<M> {
+    mapper: M,
+    config: ReplayConfig,
+}
+
+impl<M: ActionMapper> Replay<M> {
+    pub fn new(mapper: M) -> Self {
+        Self::with_config(mapper, ReplayConfig::default())
+    }
+
+    pub fn with_config(mapper: M, config: ReplayConfig) -> Self {
+        Replay { mapper, config }
+    }
+
+    pub fn run(&self, trace: &Trace<State>) -> Result<(), MbtError> {
+        let mut app = App::default();
+        let code_id = app.store_code(gungnir_contract());
+        let mut contract_addr = None;
+        let genesis = app.block_info();
+        let block_time = |time: &BigInt| -> Result<Timestamp, MbtError> {
+            self.config.time.timestamp(genesis.time, time)
+        };
+
+        for action in actions(trace) {
+            let state = action.state;
+            if state.action_taken == self.config.init_action {
+                let addr = self.instantiate(&mut app, code_id, state)?;
+                contract_addr = Some(addr);
+            } else {
+                let contract_addr = contract_addr.as_ref().ok_or_else(|| {
+                    MbtError::Setup("no init action before the first step".into())
+                })?;
+                let (sender, msg, funds) = self.message(state)?;
+
+                app.set_block(self.config.time.block(&genesis, &action.now)?);
+                let res = app.execute_contract(sender, contract_addr.clone(), &msg, &funds);
+                compare_result(&state.result, &res, &self.config.errors)
+                    .map_err(|details| action.diverged(details))?;
+                if let Ok(res) = &res {
+                    let diff = compare_messages(contract_addr, &action.messages, res);
+                    if !diff.is_empty() {
+                        return Err(action.diverged(diff.to_string()));
+                    }
+                }
+            }
+
+            if let Some(contract_addr) = &contract_addr {
+                let diff = compare_state(&app, contract_addr, state, &block_time)?;
+                if !diff.is_empty() {
+                    return Err(action.diverged(diff.to_string()));
+                }
+                let violations = check_invariants(&app, contract_addr)?;
+                if !violations.is_empty() {
+                    return Err(action.diverged(violations));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replays the trace against the reference model of `crate::model` instead of the
+    /// contract, checking that the trace agrees with the executable spec
+    pub fn check_model(&self, trace: &Trace<State>) -> Result<(), MbtError> {
+        let mut model = None;
+
+        for action in actions(trace) {
+            let state = action.state;
+            let (payouts, result) = if state.action_taken == self.config.init_action {
+                model = Some(self.reference_model(state)?);
+                (vec![], state.result.clone())
+            } else {
+                let model = model.as_mut().ok_or_else(|| {
+                    MbtError::Setup("no init action before the first step".into())
+                })?;
+                let (sender, msg, funds) = self.message(state)?;
+
+                model.now = time::ticks(&action.now)?;
+                let res = model.execute(sender.as_str(), &msg, &funds);
+                compare_model_result(&state.result, &res, &self.config.errors)
+                    .map_err(|details| action.diverged(details))?;
+                let payouts = res.as_deref().unwrap_or_default();
+                let result = res
+                    .as_ref()
+                    .map(|payouts| Response::from_payouts(payouts))
+                    .map_err(|err| err.to_string());
+                (payouts.to_vec(), result)
+            };
+
+            let Some(model) = &model else {
+                continue;
+            };
+            let diff = compare_model_messages(
+                &action.messages,
+                &Response::from_payouts(&payouts).messages,
+            );
+            if !diff.is_empty() {
+                return Err(action.diverged(diff.to_string()));
+            }
+            let reference = State::from_model(
+                model,
+                &state.action_taken,
+                state.nondet_picks.clone(),
+                result,
+                state.time.clone(),
+            );
+            let diff = compare_model_state(state, &reference);
+            if !diff.is_empty() {
+                return Err(action.diverged(diff.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reference model set up like the contract on the init action
+    fn reference_model(&self, state: &State) -> Result<Model, MbtError> {
+        let mut model = Model::new(&self.config.contract_address);
+        model.lock_period = match self.config.lock_period {
+            Some(lock_period) => lock_period,
+            None => LOCK_PERIOD / self.config.time.seconds_per_tick,
+        };
+        for (addr, balances) in &state.bank {
+            for (denom, amount) in balances {
+                model.mint(addr, denom, to_u128(amount, "bank balance")?);
+            }
+        }
+        Ok(model)
+    }
+
+    /// Sender, message and funds of the action taken in `state`
+    fn message(&self, state: &State) -> Result<(Addr, ExecuteMsg, Vec<Coin>), MbtError> {
+        let msg = self
+            .mapper
+            .execute_msg(&state.action_taken, &state.nondet_picks)
+            .ok_or_else(|| MbtError::UnknownAction {
+                action: state.action_taken.clone(),
+            })??;
+        Ok((
+            sender(&state.nondet_picks)?,
+            msg,
+            funds(&state.nondet_picks)?,
+        ))
+    }
+
+    fn instantiate(&self, app: &mut App, code_id: u64, state: &State) -> Result<Addr, MbtError> {
+        let msg = self.mapper.instantiate_msg(&state.nondet_picks)?;
+        let contract_addr = app
+            .instantiate_contract(
+                code_id,
+                sender(&state.nondet_picks)?,
+                &msg,
+                &funds(&state.nondet_picks)?,
+                "gungnir",
+                None,
+            )
+            .map_err(|err| MbtError::Setup(err.to_string()))?;
+
+        if let Some(lock_period) = self.config.lock_period {
+            let msg = msg::SudoMsg::SetLockPeriod {
+                lock_period: self.config.time.seconds(lock_period)?,
+            };
+            app.wasm_sudo(contract_addr.clone(), &msg)
+                .map_err(|err| MbtError::Setup(err.to_string()))?;
+        }
+
+        for (addr, balances) in &state.bank {
+            for (denom, amount) in balances {
+                let amount = to_u128(amount, "bank balance")?;
+                if amount == 0 {
+                    continue;
+                }
+                app.sudo(SudoMsg::Bank(BankSudo::Mint {
+                    to_address: addr.clone(),
+                    amount: vec![coin(amount, denom)],
+                }))
+                .map_err(|err| MbtError::Setup(err.to_string()))?;
+            }
+        }
+
+        Ok(contract_addr)
+    }
+}
+
+/// Action of a trace, replayed once the model has processed its messages
+struct Action<'a> {
+    step: u64,
+    /// State once the messages are processed
+    state: &'a State,
+    /// Messages of the action, listed by its first state
+    messages: Vec<SubMsg>,
+    /// Model time the action runs at, the time of the state it starts from
+    now: BigInt,
+}
+
+impl Action<'_> {
+    fn diverged(&self, details: String) -> MbtError {
+        MbtError::Divergence {
+            step: self.step,
+            action: self.state.action_taken.clone(),
+            picks: format!("{:?}", self.state.nondet_picks),
+            details,
+        }
+    }
+}
+
+fn actions(trace: &Trace<State>) -> Vec<Action<'_>> {
+    let mut actions = vec![];
+    let mut now = BigInt::from(0);
+    let mut pending = None;
+
+    for (index, state) in trace.states.iter().enumerate() {
+        let step = state.meta.index.unwrap_or(index as u64);
+        let state = &state.value;
+        if state.has_pending_messages() {
+            if let Ok(response) = &state.result {
+                pending.get_or_insert_with(|| response.messages.clone());
+            }
+            continue;
+        }
+
+        actions.push(Action {
+            step,
+            state,
+            messages: pending.take().unwrap_or_default(),
+            now: now.clone(),
+        });
+        now = state.time.clone();
+    }
+    actions
+}
+
+/// Safety properties of `crate::invariants` broken by the contract, one per line
+fn check_invariants(app: &App, contract_addr: &Addr) -> Result<String, MbtError> {
+    let balance = app
+        .wrap()
+        .query_balance(contract_addr, contract::DENOM)
+        .map_err(|err| MbtError::Setup(err.to_string()))?;
+    let violations = invariants::check_records(app.dump_wasm_raw(contract_addr), balance.amount)
+        .map_err(|err| MbtError::Setup(err.to_string()))?;
+    Ok(violations
+        .iter()
+        .map(|violation| format!("  invariant: {violation}"))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn sender(picks: &NondetPicks) -> Result<Addr, MbtError> {
+    picks
+        .sender
+        .as_ref()
+        .map(Addr::unchecked)
+        .ok_or_else(|| MbtError::MissingPick {
+            pick: "sender".to_string(),
+        })
+}
+
+fn funds(picks: &NondetPicks) -> Result<Vec<Coin>, MbtError> {
+    match (&picks.amount, &picks.denom) {
+        (Some(amount), Some(denom)) => {
+            let amount = to_u128(amount, "amount")?;
+            Ok(if amount == 0 {
+                vec![]
+            } else {
+                vec![coin(amount, denom)]
+            })
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+fn compare_result(
+    expected: &Result<Response, String>,
+    actual: &anyhow::Result<AppResponse>,
+    errors: &ErrorTable,
+) -> Result<(), String> {
+    match (expected, actual) {
+        (Ok(_), Err(err)) => Err(format!(
+            "  model: success\n  contract: {}",
+            err.root_cause()
+        )),
+        (Err(err), Ok(_)) => Err(format!("  model: {err}\n  contract: success")),
+        (Err(model_error), Err(err)) => errors.check(model_error, err),
+        (Ok(_), Ok(_)) => Ok(()),
+    }
+}
+
+fn compare_model_result(
+    expected: &Result<Response, String>,
+    actual: &Result<Vec<BankMsg>, ModelError>,
+    errors: &ErrorTable,
+) -> Result<(), String> {
+    match (expected, actual) {
+        (Ok(_), Err(err)) => Err(format!("  model: success\n  reference: {err}")),
+        (Err(err), Ok(_)) => Err(format!("  model: {err}\n  reference: success")),
+        (Err(model_error), Err(err)) => errors.check_reference(model_error, &err.to_string()),
+        (Ok(_), Ok(_)) => Ok(()),
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ContractError;
+
+/// Whether a contract error is the variant a model error stands for
+pub type ErrorMatcher = fn(&ContractError) -> bool;
+
+macro_rules! variant {
+    ($variant:pat) => {
+        |err: &ContractError| matches!(err, $variant)
+    };
+}
+
+/// Table from the error strings of the model to `ContractError` variants
+#[derive(Clone, Debug, Default)]
+pub struct ErrorTable {
+    matchers: HashMap<String, ErrorMatcher>,
+    /// Whether model errors missing from the table fail the replay
+    strict: bool,
+}
+
+impl ErrorTable {
+    /// Empty table, accepting any contract error for any model error
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Table mapping each variant name of `ContractError` to the variant
+    pub fn gungnir() -> Self {
+        ErrorTable::new()
+            .map("Std", variant!(ContractError::Std(_)))
+            .map("Payment", variant!(ContractError::Payment(_)))
+            .map("Unauthorized", variant!(ContractError::Unauthorized {}))
+            .map(
+                "InsufficientFunds",
+                variant!(ContractError::InsufficientFunds {}),
+            )
+            .map(
+                "InsufficientVotingPower",
+                variant!(ContractError::InsufficientVotingPower {}),
+            )
+            .map("Paused", variant!(ContractError::Paused {}))
+            .map("Frozen", variant!(ContractError::Frozen { .. }))
+            .map(
+                "NotAllowlisted",
+                variant!(ContractError::NotAllowlisted { .. }),
+            )
+            .map("CapExceeded", variant!(ContractError::CapExceeded { .. }))
+            .map(
+                "StakeTooSmall",
+                variant!(ContractError::StakeTooSmall { .. }),
+            )
+            .map(
+                "WithdrawalLimitExceeded",
+                variant!(ContractError::WithdrawalLimitExceeded { .. }),
+            )
+            .map(
+                "ReceiptsRequired",
+                variant!(ContractError::ReceiptsRequired {}),
+            )
+    }
+
+    pub fn map(mut self, model_error: &str, matcher: ErrorMatcher) -> Self {
+        self.matchers.insert(model_error.to_string(), matcher);
+        self
+    }
+
+    /// Whether `model_error` is mapped to a variant
+    pub fn contains(&self, model_error: &str) -> bool {
+        self.matchers.contains_key(model_error)
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Checks that the contract failed with the variant the model error stands for
+    pub fn check(&self, model_error: &str, err: &anyhow::Error) -> Result<(), String> {
+        let Some(matcher) = self.matchers.get(model_error) else {
+            return self.unmapped(model_error, "contract", err.root_cause());
+        };
+
+        match err
+            .chain()
+            .find_map(|err| err.downcast_ref::<ContractError>())
+        {
+            Some(contract_error) if matcher(contract_error) => Ok(()),
+            Some(contract_error) => Err(format!(
+                "  model: {model_error}\n  contract: {contract_error:?} ({contract_error})"
+            )),
+            None => Err(format!(
+                "  model: {model_error}\n  contract: {} (not a ContractError)",
+                err.root_cause()
+            )),
+        }
+    }
+
+    /// Checks that the reference model failed with the error of the model, both being
+    /// named after the contract errors they stand for
+    pub fn check_reference(&self, model_error: &str, err: &str) -> Result<(), String> {
+        if !self.contains(model_error) {
+            return self.unmapped(model_error, "reference", err);
+        }
+        if model_error != err {
+            return Err(format!("  model: {model_error}\n  reference: {err}"));
+        }
+        Ok(())
+    }
+
+    /// Outcome for a model error missing from the table, failing only in strict mode
+    fn unmapped(
+        &self,
+        model_error: &str,
+        against: &str,
+        err: impl fmt::Display,
+    ) -> Result<(), String> {
+        if !self.strict {
+            return Ok(());
+        }
+        Err(format!(
+            "  model error {model_error:?} has no matching ContractError variant\n  {against}: {err}"
+        ))
+    }
+}
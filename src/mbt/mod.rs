@@ -0,0 +1,48 @@
+//! Replays ITF traces of the Quint model against the contract in `cw-multi-test`.
+//! Only built with the `mbt` feature.
+
+mod compare;
+mod driver;
+mod errors;
+pub mod runner;
+pub mod shrink;
+mod time;
+pub mod trace;
+
+use thiserror::Error;
+
+pub use compare::StateDiff;
+pub use driver::{gungnir_contract, ActionMapper, ActionRegistry, Replay, ReplayConfig};
+pub use errors::{ErrorMatcher, ErrorTable};
+pub use runner::{load_trace, RunSummary, TraceRunner};
+pub use shrink::{shrink, Shrunk};
+pub use time::TimeMapping;
+
+#[derive(Error, Debug)]
+pub enum MbtError {
+    #[error("No mapping registered for action {action}")]
+    UnknownAction { action: String },
+
+    #[error("Missing nondet pick {pick}")]
+    MissingPick { pick: String },
+
+    #[error("Invalid value {value} for {name}")]
+    InvalidValue { name: String, value: String },
+
+    #[error("Could not load trace {path}: {reason}")]
+    Load { path: String, reason: String },
+
+    #[error("Could not write trace {path}: {reason}")]
+    Write { path: String, reason: String },
+
+    #[error("Contract setup failed: {0}")]
+    Setup(String),
+
+    #[error("Step {step} ({action}) diverged from the model\n  nondet picks: {picks}\n{details}")]
+    Divergence {
+        step: u64,
+        action: String,
+        picks: String,
+        details: String,
+    },
+}
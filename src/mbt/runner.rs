@@ -0,0 +1,210 @@
+//! Discovers the ITF traces under a directory and replays each of them
+
+use std::fmt;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use itf::Trace;
+use serde::de::DeserializeOwned;
+
+use super::{shrink, MbtError};
+
+/// Extension of the trace files picked up by the runner
+pub const TRACE_EXTENSION: &str = ".itf.json";
+/// Environment variable restricting the run to the traces whose name or seed contains its value
+pub const TRACE_FILTER_VAR: &str = "MBT_TRACE_FILTER";
+/// Environment variable naming the directory diverging traces are shrunk into
+pub const SHRINK_DIR_VAR: &str = "MBT_SHRINK_DIR";
+
+/// Replays every trace under a directory, reporting each trace as its own case
+pub struct TraceRunner {
+    dir: PathBuf,
+    filter: Option<String>,
+    shrink_dir: Option<PathBuf>,
+}
+
+impl TraceRunner {
+    /// Runner over `dir`, filtered by `MBT_TRACE_FILTER` and shrinking into
+    /// `MBT_SHRINK_DIR` when they are set
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+        TraceRunner {
+            dir: dir.into(),
+            filter: var(TRACE_FILTER_VAR),
+            shrink_dir: var(SHRINK_DIR_VAR).map(PathBuf::from),
+        }
+    }
+
+    pub fn filter(mut self, filter: Option<String>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Directory diverging traces are shrunk into, under the name they have in the
+    /// runner directory
+    pub fn shrink_into(mut self, dir: Option<PathBuf>) -> Self {
+        self.shrink_dir = dir;
+        self
+    }
+
+    /// Trace files under the directory, sorted by path
+    pub fn discover(&self) -> Result<Vec<PathBuf>, MbtError> {
+        let mut paths = vec![];
+        collect_traces(&self.dir, &mut paths)?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Replays the matching traces with `replay`. A trace fails if it cannot be loaded,
+    /// if `replay` returns an error or if it panics.
+    pub fn run<S: DeserializeOwned>(
+        &self,
+        replay: impl Fn(&Trace<S>) -> Result<(), MbtError>,
+    ) -> Result<RunSummary, MbtError> {
+        let paths = self.discover()?;
+        if paths.is_empty() {
+            return Err(MbtError::Setup(format!(
+                "no {TRACE_EXTENSION} traces under {}",
+                self.dir.display()
+            )));
+        }
+
+        let mut summary = RunSummary::default();
+        for path in paths {
+            let name = path
+                .strip_prefix(&self.dir)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            let trace = load_trace::<S>(&path);
+            if !self.matches(&name, trace.as_ref().ok()) {
+                summary.filtered += 1;
+                continue;
+            }
+            let trace = match trace {
+                Ok(trace) => trace,
+                Err(err) => {
+                    summary.record(name, Err(err.to_string()));
+                    continue;
+                }
+            };
+
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| replay(&trace)))
+                .map_err(|panic| panic_message(&panic))
+                .and_then(|res| {
+                    res.map_err(|err| match (&err, &self.shrink_dir) {
+                        (MbtError::Divergence { .. }, Some(dir)) => {
+                            let shrunk = shrink_trace(&path, &dir.join(&name), &replay);
+                            format!("{err}\n{}", shrunk.unwrap_or_else(|err| err.to_string()))
+                        }
+                        _ => err.to_string(),
+                    })
+                });
+            summary.record(name, outcome);
+        }
+
+        println!("{summary}");
+        Ok(summary)
+    }
+
+    /// Whether the file name or the seed recorded in the trace metadata contains the filter
+    fn matches<S>(&self, name: &str, trace: Option<&Trace<S>>) -> bool {
+        let Some(filter) = &self.filter else {
+            return true;
+        };
+        let seed = trace.and_then(|trace| trace.meta.other.get("seed"));
+        name.contains(filter.as_str()) || seed.is_some_and(|seed| seed.contains(filter.as_str()))
+    }
+}
+
+/// Loads a single trace file
+pub fn load_trace<S: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Trace<S>, MbtError> {
+    let path = path.as_ref();
+    let load_error = |reason: String| MbtError::Load {
+        path: path.display().to_string(),
+        reason,
+    };
+    let data = fs::read_to_string(path).map_err(|err| load_error(err.to_string()))?;
+    itf::trace_from_str(&data).map_err(|err| load_error(err.to_string()))
+}
+
+/// Shrinks the diverging trace at `path` into `target`, describing the outcome
+fn shrink_trace<S: DeserializeOwned>(
+    path: &Path,
+    target: &Path,
+    replay: impl Fn(&Trace<S>) -> Result<(), MbtError>,
+) -> Result<String, MbtError> {
+    let shrunk = shrink::shrink(&shrink::load_raw_trace(path)?, replay)?;
+    shrink::write_trace(target, &shrunk.trace)?;
+    Ok(format!("{shrunk}: {}", target.display()))
+}
+
+fn collect_traces(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), MbtError> {
+    let load_error = |err: std::io::Error| MbtError::Load {
+        path: dir.display().to_string(),
+        reason: err.to_string(),
+    };
+    for entry in fs::read_dir(dir).map_err(load_error)? {
+        let path = entry.map_err(load_error)?.path();
+        if path.is_dir() {
+            collect_traces(&path, paths)?;
+        } else if path.to_string_lossy().ends_with(TRACE_EXTENSION) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        format!("panicked: {msg}")
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        format!("panicked: {msg}")
+    } else {
+        "panicked".to_string()
+    }
+}
+
+/// Outcome of a run, listing the traces that passed and the ones that failed
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub passed: Vec<String>,
+    pub failed: Vec<(String, String)>,
+    /// Traces skipped by the filter
+    pub filtered: usize,
+}
+
+impl RunSummary {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    fn record(&mut self, name: String, outcome: Result<(), String>) {
+        match outcome {
+            Ok(()) => {
+                println!("trace {name} ... ok");
+                self.passed.push(name);
+            }
+            Err(err) => {
+                println!("trace {name} ... FAILED");
+                self.failed.push((name, err));
+            }
+        }
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, err) in &self.failed {
+            writeln!(f, "---- {name} ----\n{err}\n")?;
+        }
+        write!(
+            f,
+            "traces: {} passed; {} failed; {} filtered out",
+            self.passed.len(),
+            self.failed.len(),
+            self.filtered
+        )
+    }
+}
@@ -0,0 +1,351 @@
+//! Minimizes a diverging trace into a regression that still reproduces the divergence.
+//!
+//! The trace is cut right after the diverging step, then runs of states are deleted
+//! while the replay keeps diverging on the same action and fields at its last step.
+//! States after a deleted run have the integer changes made by the run taken back out
+//! of their state variables, so that deleting e.g. an unrelated deposit leaves
+//! consistent balances behind.
+
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use itf::Trace;
+use num_bigint::BigInt;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use super::MbtError;
+
+/// Variables of a state describing the step rather than the state it leads to
+const STEP_VARS: [&str; 4] = ["#meta", "action_taken", "nondet_picks", "result"];
+
+/// Trace reduced by `shrink`, with the divergence it reproduces
+#[derive(Debug)]
+pub struct Shrunk {
+    pub trace: Trace<Value>,
+    pub divergence: MbtError,
+    /// States of the original trace
+    pub original_len: usize,
+}
+
+impl fmt::Display for Shrunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "shrunk {} states to {}",
+            self.original_len,
+            self.trace.states.len()
+        )
+    }
+}
+
+/// Shrinks a raw ITF trace that diverges under `replay`, keeping the first action,
+/// which sets up the contract
+pub fn shrink<S: DeserializeOwned>(
+    trace: &Trace<Value>,
+    replay: impl Fn(&Trace<S>) -> Result<(), MbtError>,
+) -> Result<Shrunk, MbtError> {
+    let divergence = match replay_raw(trace, &replay) {
+        Some(Err(err @ MbtError::Divergence { .. })) => err,
+        Some(Err(err)) => return Err(err),
+        Some(Ok(())) => {
+            return Err(MbtError::Setup(
+                "the trace replays without diverging".into(),
+            ))
+        }
+        None => return Err(MbtError::Setup("the trace cannot be replayed".into())),
+    };
+    let target = Fingerprint::of(&divergence).expect("divergences have a fingerprint");
+
+    // the steps after the divergence are never replayed
+    let end = trace
+        .states
+        .iter()
+        .enumerate()
+        .position(|(position, state)| state.meta.index.unwrap_or(position as u64) == target.step)
+        .unwrap_or(trace.states.len() - 1);
+    let mut states = trace.states[..=end].to_vec();
+    let mut best = renumbered(trace, states.clone());
+    let Some(mut divergence) = reproduce(&best, &replay, &target) else {
+        // the replay depends on the states after the divergence, keep them all
+        return Ok(Shrunk {
+            trace: trace.clone(),
+            divergence,
+            original_len: trace.states.len(),
+        });
+    };
+
+    let mut size = (actions(&states).len() / 2).max(1);
+    loop {
+        let mut deleted = false;
+        // runs are deleted from the end, where steps are the least likely to be needed,
+        // never deleting the first action, which sets up the contract, nor the last one
+        let mut end = actions(&states).len() - 1;
+        while end > 1 {
+            let actions = actions(&states);
+            let start = end.saturating_sub(size).max(1);
+            let run = actions[start].start..actions[end - 1].end;
+            let candidate = renumbered(trace, without(&states, run));
+            match reproduce(&candidate, &replay, &target) {
+                Some(err) => {
+                    states = candidate.states.clone();
+                    best = candidate;
+                    divergence = err;
+                    deleted = true;
+                    end = start;
+                }
+                None => end -= 1,
+            }
+        }
+
+        if !deleted {
+            if size == 1 {
+                break;
+            }
+            size /= 2;
+        }
+    }
+
+    Ok(Shrunk {
+        trace: best,
+        divergence,
+        original_len: trace.states.len(),
+    })
+}
+
+/// Loads a trace file as raw ITF values, as taken by `shrink`
+pub fn load_raw_trace(path: impl AsRef<Path>) -> Result<Trace<Value>, MbtError> {
+    let path = path.as_ref();
+    let load_error = |reason: String| MbtError::Load {
+        path: path.display().to_string(),
+        reason,
+    };
+    let data = fs::read_to_string(path).map_err(|err| load_error(err.to_string()))?;
+    serde_json::from_str(&data).map_err(|err| load_error(err.to_string()))
+}
+
+/// Writes a raw trace as an ITF file, creating its directory if needed
+pub fn write_trace(path: impl AsRef<Path>, trace: &Trace<Value>) -> Result<(), MbtError> {
+    let path = path.as_ref();
+    let write_error = |reason: String| MbtError::Write {
+        path: path.display().to_string(),
+        reason,
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| write_error(err.to_string()))?;
+    }
+    let mut value = serde_json::to_value(trace).map_err(|err| write_error(err.to_string()))?;
+    // unset optional fields are left out rather than written as null
+    if let Some(trace) = value.as_object_mut() {
+        trace.retain(|_, field| !field.is_null());
+        if let Some(Value::Object(meta)) = trace.get_mut("#meta") {
+            meta.retain(|_, field| !field.is_null());
+        }
+    }
+    let data = serde_json::to_string_pretty(&value).map_err(|err| write_error(err.to_string()))?;
+    fs::write(path, data + "\n").map_err(|err| write_error(err.to_string()))
+}
+
+/// Step, action and failing fields identifying a divergence
+#[derive(Debug)]
+struct Fingerprint {
+    step: u64,
+    action: String,
+    fields: Vec<String>,
+}
+
+impl Fingerprint {
+    fn of(err: &MbtError) -> Option<Self> {
+        let MbtError::Divergence {
+            step,
+            action,
+            details,
+            ..
+        } = err
+        else {
+            return None;
+        };
+        // values may change as steps are deleted, the fields that differ may not
+        let fields = details
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect();
+        Some(Fingerprint {
+            step: *step,
+            action: action.clone(),
+            fields,
+        })
+    }
+}
+
+/// Divergence of `trace` if it matches `target` at the last step of the trace
+fn reproduce<S: DeserializeOwned>(
+    trace: &Trace<Value>,
+    replay: impl Fn(&Trace<S>) -> Result<(), MbtError>,
+    target: &Fingerprint,
+) -> Option<MbtError> {
+    let err = replay_raw(trace, replay)?.err()?;
+    let fingerprint = Fingerprint::of(&err)?;
+    let last = trace.states.len() as u64 - 1;
+    let reproduced = fingerprint.step == last
+        && fingerprint.action == target.action
+        && fingerprint.fields == target.fields;
+    reproduced.then_some(err)
+}
+
+/// Outcome of replaying a raw trace, `None` if it cannot be decoded or the replay panics
+fn replay_raw<S: DeserializeOwned>(
+    trace: &Trace<Value>,
+    replay: impl Fn(&Trace<S>) -> Result<(), MbtError>,
+) -> Option<Result<(), MbtError>> {
+    let trace = trace.clone().decode::<S>().ok()?;
+    panic::catch_unwind(AssertUnwindSafe(|| replay(&trace))).ok()
+}
+
+/// Copy of `trace` over `states`, indexed from zero
+fn renumbered(trace: &Trace<Value>, mut states: Vec<itf::State<Value>>) -> Trace<Value> {
+    for (index, state) in states.iter_mut().enumerate() {
+        state.meta.index = Some(index as u64);
+    }
+    Trace {
+        meta: trace.meta.clone(),
+        params: trace.params.clone(),
+        vars: trace.vars.clone(),
+        loop_index: trace.loop_index,
+        states,
+    }
+}
+
+/// Ranges of states making up each action, the states that queue messages followed
+/// by the state that processes them
+fn actions(states: &[itf::State<Value>]) -> Vec<Range<usize>> {
+    let mut actions = vec![];
+    let mut start = 0;
+    for (index, state) in states.iter().enumerate() {
+        let result = &state.value["result"];
+        let pending = result["tag"] == "Ok"
+            && result["value"]["messages"]
+                .as_array()
+                .is_some_and(|messages| !messages.is_empty());
+        if !pending {
+            actions.push(start..index + 1);
+            start = index + 1;
+        }
+    }
+    if start < states.len() {
+        actions.push(start..states.len());
+    }
+    actions
+}
+
+/// `states` without the states in `run`, the changes of the run taken out of the
+/// states after it
+fn without(states: &[itf::State<Value>], run: Range<usize>) -> Vec<itf::State<Value>> {
+    let before = &states[run.start - 1].value;
+    let after = &states[run.end - 1].value;
+    let mut kept = states[..run.start].to_vec();
+    for state in &states[run.end..] {
+        let mut state = state.clone();
+        if let (Value::Object(vars), Value::Object(before), Value::Object(after)) =
+            (&mut state.value, before, after)
+        {
+            for (name, after) in after {
+                if STEP_VARS.contains(&name.as_str()) {
+                    continue;
+                }
+                if let Some(var) = vars.get_mut(name) {
+                    revert(var, before.get(name), after);
+                }
+            }
+        }
+        kept.push(state);
+    }
+    kept
+}
+
+/// Subtracts the change from `before` to `after` from `value`, for integers nested in
+/// records and maps. Map entries added by the change are dropped once they are zero.
+fn revert(value: &mut Value, before: Option<&Value>, after: &Value) {
+    if let Some(after) = bigint(after) {
+        let before = before.and_then(bigint).unwrap_or_default();
+        if let Some(current) = bigint(value) {
+            *value = json!({ "#bigint": (current - (after - before)).to_string() });
+        }
+        return;
+    }
+
+    match (value, after) {
+        (Value::Object(value), Value::Object(after)) if after.contains_key("#map") => {
+            let (Some(Value::Array(entries)), Some(Value::Array(changed))) =
+                (value.get_mut("#map"), after.get("#map"))
+            else {
+                return;
+            };
+            let before = before
+                .and_then(|before| before.get("#map"))
+                .and_then(Value::as_array);
+            for pair in changed {
+                let Some([key, after]) = pair.as_array().map(Vec::as_slice) else {
+                    continue;
+                };
+                let previous = before.and_then(|before| entry(before, key));
+                for current in entries.iter_mut() {
+                    if let Some([current_key, current]) =
+                        current.as_array_mut().map(Vec::as_mut_slice)
+                    {
+                        if current_key == key {
+                            revert(current, previous, after);
+                        }
+                    }
+                }
+                if previous.is_none() {
+                    entries.retain(|pair| {
+                        !matches!(pair.as_array().map(Vec::as_slice), Some([current_key, current])
+                            if current_key == key && is_zero(current))
+                    });
+                }
+            }
+        }
+        (Value::Object(value), Value::Object(after)) => {
+            for (name, after) in after {
+                if name.starts_with('#') {
+                    continue;
+                }
+                if let Some(current) = value.get_mut(name) {
+                    revert(current, before.and_then(|before| before.get(name)), after);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Value of `key` in the entries of an ITF map
+fn entry<'a>(entries: &'a [Value], key: &Value) -> Option<&'a Value> {
+    entries
+        .iter()
+        .find_map(|pair| match pair.as_array().map(Vec::as_slice) {
+            Some([entry_key, value]) if entry_key == key => Some(value),
+            _ => None,
+        })
+}
+
+fn bigint(value: &Value) -> Option<BigInt> {
+    value.get("#bigint")?.as_str()?.parse().ok()
+}
+
+/// Whether every integer in `value` is zero
+fn is_zero(value: &Value) -> bool {
+    if let Some(value) = bigint(value) {
+        return value == BigInt::default();
+    }
+    match value {
+        Value::Object(fields) => fields.values().all(is_zero),
+        Value::Array(items) => items.iter().all(is_zero),
+        _ => true,
+    }
+}
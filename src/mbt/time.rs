@@ -0,0 +1,67 @@
+//! Mapping from the time of the model to the block time and height of the chain
+
+use cosmwasm_std::{BlockInfo, Timestamp};
+use num_bigint::BigInt;
+
+use super::trace::to_u128;
+use super::MbtError;
+
+/// Scales units of model time to block seconds and heights
+#[derive(Clone, Debug)]
+pub struct TimeMapping {
+    /// Seconds of block time per unit of model time
+    pub seconds_per_tick: u64,
+    /// Blocks produced per unit of model time
+    pub blocks_per_tick: u64,
+}
+
+impl Default for TimeMapping {
+    fn default() -> Self {
+        TimeMapping {
+            seconds_per_tick: 1,
+            blocks_per_tick: 1,
+        }
+    }
+}
+
+impl TimeMapping {
+    /// Seconds of block time spanned by `ticks` units of model time
+    pub fn seconds(&self, ticks: u64) -> Result<u64, MbtError> {
+        ticks
+            .checked_mul(self.seconds_per_tick)
+            .ok_or_else(|| invalid_time(ticks))
+    }
+
+    /// Block time at model time `time`, counted from `genesis`
+    pub fn timestamp(&self, genesis: Timestamp, time: &BigInt) -> Result<Timestamp, MbtError> {
+        Ok(genesis.plus_seconds(self.seconds(ticks(time)?)?))
+    }
+
+    /// Block at model time `time`, counted from `genesis`
+    pub fn block(&self, genesis: &BlockInfo, time: &BigInt) -> Result<BlockInfo, MbtError> {
+        let ticks = ticks(time)?;
+        let blocks = ticks
+            .checked_mul(self.blocks_per_tick)
+            .ok_or_else(|| invalid_time(ticks))?;
+        Ok(BlockInfo {
+            height: genesis.height + blocks,
+            time: self.timestamp(genesis.time, time)?,
+            chain_id: genesis.chain_id.clone(),
+        })
+    }
+}
+
+/// Units of model time in `time`
+pub(super) fn ticks(time: &BigInt) -> Result<u64, MbtError> {
+    u64::try_from(to_u128(time, "time")?).map_err(|_| MbtError::InvalidValue {
+        name: "time".to_string(),
+        value: time.to_string(),
+    })
+}
+
+fn invalid_time(ticks: u64) -> MbtError {
+    MbtError::InvalidValue {
+        name: "time".to_string(),
+        value: ticks.to_string(),
+    }
+}
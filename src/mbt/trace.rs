@@ -0,0 +1,227 @@
+//! States of the ITF traces produced by the Quint model
+
+use std::collections::HashMap;
+
+use itf::de::{self, As};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use serde::Deserialize;
+
+use super::MbtError;
+use crate::model::Model;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserInfo {
+    pub total_tokens: BigInt,
+    pub voting_power: BigInt,
+    pub released_time: BigInt,
+    /// Left out by models without lock tiers, see `UserInfo::locked`
+    #[serde(default)]
+    pub locked_tokens: Option<BigInt>,
+}
+
+impl UserInfo {
+    /// Tokens backing voting power, one per unit of voting power in models without
+    /// lock tiers
+    pub fn locked(&self) -> &BigInt {
+        self.locked_tokens.as_ref().unwrap_or(&self.voting_power)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ContractState {
+    pub voting_power: HashMap<String, UserInfo>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NondetPicks {
+    #[serde(with = "As::<de::Option::<_>>")]
+    pub sender: Option<String>,
+
+    #[serde(with = "As::<de::Option::<_>>")]
+    pub denom: Option<String>,
+
+    #[serde(with = "As::<de::Option::<_>>")]
+    pub amount: Option<BigInt>,
+
+    #[serde(with = "As::<de::Option::<_>>")]
+    pub message_amount: Option<BigInt>,
+
+    #[serde(with = "As::<de::Option::<_>>")]
+    pub message_lock_amount: Option<BigInt>,
+
+    #[serde(with = "As::<de::Option::<_>>")]
+    pub message_unlock_amount: Option<BigInt>,
+}
+
+impl NondetPicks {
+    /// Value of a numeric pick, failing if the model did not make it
+    pub fn number(pick: &Option<BigInt>, name: &str) -> Result<u128, MbtError> {
+        pick.as_ref()
+            .ok_or_else(|| MbtError::MissingPick {
+                pick: name.to_string(),
+            })
+            .and_then(|value| to_u128(value, name))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Coin {
+    pub denom: String,
+    pub amount: BigInt,
+}
+
+/// Submessage of a model response. Reply settings are not replayed.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SubMsg {
+    pub id: BigInt,
+    pub msg: CosmosMsg,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "tag", content = "value")]
+pub enum CosmosMsg {
+    #[serde(rename = "CosmosMsg_Bank")]
+    Bank(BankMsg),
+    #[serde(rename = "CosmosMsg_Wasm")]
+    Wasm(WasmMsg),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "tag", content = "value")]
+pub enum BankMsg {
+    #[serde(rename = "BankMsg_Send")]
+    Send {
+        to_address: String,
+        amount: Vec<Coin>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "tag", content = "value")]
+pub enum WasmMsg {
+    #[serde(rename = "WasmMsg_Instantiate")]
+    Instantiate {
+        code_id: BigInt,
+        funds: Vec<Coin>,
+        label: String,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Attribute {
+    pub key: String,
+    pub value: QuintSerializedValue,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "tag", content = "value")]
+pub enum QuintSerializedValue {
+    FromInt(BigInt),
+    FromStr(String),
+    FromListInt(Vec<BigInt>),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub messages: Vec<SubMsg>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Response {
+    /// Response sending the payouts of the reference model
+    pub fn from_payouts(payouts: &[cosmwasm_std::BankMsg]) -> Self {
+        let messages = payouts
+            .iter()
+            .filter_map(|payout| match payout {
+                cosmwasm_std::BankMsg::Send { to_address, amount } => Some(SubMsg {
+                    id: BigInt::from(0),
+                    msg: CosmosMsg::Bank(BankMsg::Send {
+                        to_address: to_address.clone(),
+                        amount: amount
+                            .iter()
+                            .map(|coin| Coin {
+                                denom: coin.denom.clone(),
+                                amount: BigInt::from(coin.amount.u128()),
+                            })
+                            .collect(),
+                    }),
+                }),
+                _ => None,
+            })
+            .collect();
+        Response {
+            messages,
+            attributes: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct State {
+    pub contract_state: ContractState,
+    pub bank: HashMap<String, HashMap<String, BigInt>>,
+    #[serde(with = "As::<de::Result::<_, _>>")]
+    pub result: Result<Response, String>,
+    pub action_taken: String,
+    pub nondet_picks: NondetPicks,
+    pub time: BigInt,
+}
+
+impl State {
+    /// State of the reference model once `action_taken` and its messages are processed
+    pub fn from_model(
+        model: &Model,
+        action_taken: &str,
+        nondet_picks: NondetPicks,
+        result: Result<Response, String>,
+        time: BigInt,
+    ) -> Self {
+        let voting_power = model
+            .positions
+            .iter()
+            .map(|(user, position)| {
+                let info = UserInfo {
+                    total_tokens: BigInt::from(position.total_tokens),
+                    voting_power: BigInt::from(position.voting_power),
+                    released_time: BigInt::from(position.released_time),
+                    locked_tokens: Some(BigInt::from(position.locked_tokens)),
+                };
+                (user.clone(), info)
+            })
+            .collect();
+        let bank = model
+            .bank
+            .iter()
+            .map(|(addr, balances)| {
+                let balances = balances
+                    .iter()
+                    .map(|(denom, amount)| (denom.clone(), BigInt::from(*amount)))
+                    .collect();
+                (addr.clone(), balances)
+            })
+            .collect();
+
+        State {
+            contract_state: ContractState { voting_power },
+            bank,
+            result,
+            action_taken: action_taken.to_string(),
+            nondet_picks,
+            time,
+        }
+    }
+
+    /// Whether the model still has messages of the last action to process
+    pub fn has_pending_messages(&self) -> bool {
+        matches!(&self.result, Ok(response) if !response.messages.is_empty())
+    }
+}
+
+/// Converts a model integer, failing on values the contract cannot represent
+pub fn to_u128(value: &BigInt, name: &str) -> Result<u128, MbtError> {
+    value.to_u128().ok_or_else(|| MbtError::InvalidValue {
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
@@ -0,0 +1,225 @@
+//! Executable reference model of the contract over plain maps, covering deposits,
+//! withdrawals, staking, the bank and time under the default configuration.
+//! Times can be counted in any unit as long as `now` and `lock_period` agree,
+//! e.g. block seconds for differential tests or model ticks for ITF traces.
+
+use std::collections::BTreeMap;
+
+use cosmwasm_std::{BankMsg, Coin, Uint128};
+use thiserror::Error;
+
+use crate::contract::{DENOM, LOCK_PERIOD};
+use crate::msg::ExecuteMsg;
+
+/// Position of a user, as stored by the contract
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub total_tokens: u128,
+    pub locked_tokens: u128,
+    pub voting_power: u128,
+    /// Time the lock is released, zero if the user never staked
+    pub released_time: u64,
+}
+
+impl Position {
+    /// Tokens neither locked nor backing voting power
+    pub fn available(&self) -> u128 {
+        self.total_tokens - self.locked_tokens.min(self.total_tokens)
+    }
+
+    /// Share of the voting power backed by `amount` of the locked tokens
+    pub fn voting_power_of(&self, amount: u128) -> u128 {
+        if amount == self.locked_tokens {
+            self.voting_power
+        } else {
+            Uint128::new(self.voting_power)
+                .multiply_ratio(amount, self.locked_tokens)
+                .u128()
+        }
+    }
+}
+
+/// Failures of the model, named after the `ContractError` variants they stand for
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ModelError {
+    /// The sender has no position
+    #[error("Std")]
+    Std,
+
+    #[error("Payment")]
+    Payment,
+
+    #[error("InsufficientFunds")]
+    InsufficientFunds,
+
+    #[error("InsufficientVotingPower")]
+    InsufficientVotingPower,
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    /// The bank rejects a transfer, outside of the contract
+    #[error("Bank")]
+    Bank,
+
+    /// The message is not covered by the model
+    #[error("Unsupported")]
+    Unsupported,
+}
+
+#[derive(Clone, Debug)]
+pub struct Model {
+    /// Bank address of the contract
+    pub contract: String,
+    pub now: u64,
+    pub lock_period: u64,
+    pub positions: BTreeMap<String, Position>,
+    /// Balances by address and denom
+    pub bank: BTreeMap<String, BTreeMap<String, u128>>,
+}
+
+impl Model {
+    pub fn new(contract: impl Into<String>) -> Self {
+        Model {
+            contract: contract.into(),
+            now: 0,
+            lock_period: LOCK_PERIOD,
+            positions: BTreeMap::new(),
+            bank: BTreeMap::new(),
+        }
+    }
+
+    pub fn balance(&self, addr: &str, denom: &str) -> u128 {
+        self.bank
+            .get(addr)
+            .and_then(|balances| balances.get(denom))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn mint(&mut self, addr: &str, denom: &str, amount: u128) {
+        *self
+            .bank
+            .entry(addr.to_string())
+            .or_default()
+            .entry(denom.to_string())
+            .or_default() += amount;
+    }
+
+    pub fn advance_time(&mut self, time: u64) {
+        self.now += time;
+    }
+
+    /// Executes `msg` the way the chain does: `funds` move to the contract first, the
+    /// payouts of the contract are sent afterwards and nothing changes on failure.
+    /// Returns the payouts.
+    pub fn execute(
+        &mut self,
+        sender: &str,
+        msg: &ExecuteMsg,
+        funds: &[Coin],
+    ) -> Result<Vec<BankMsg>, ModelError> {
+        let mut next = self.clone();
+        let contract = self.contract.clone();
+        if !funds.is_empty() {
+            next.transfer(sender, &contract, funds)?;
+        }
+
+        let payouts = match msg {
+            ExecuteMsg::Deposit {} => next.deposit(sender, funds).map(|_| vec![]),
+            ExecuteMsg::Withdraw { amount } => next.withdraw(sender, amount.u128()),
+            ExecuteMsg::Stake {
+                lock_amount,
+                tier: None,
+            } => next.stake(sender, *lock_amount).map(|_| vec![]),
+            ExecuteMsg::Unstake { unlock_amount } => {
+                next.unstake(sender, *unlock_amount).map(|_| vec![])
+            }
+            _ => Err(ModelError::Unsupported),
+        }?;
+
+        for payout in &payouts {
+            if let BankMsg::Send { to_address, amount } = payout {
+                next.transfer(&contract, to_address, amount)?;
+            }
+        }
+        *self = next;
+        Ok(payouts)
+    }
+
+    fn deposit(&mut self, sender: &str, funds: &[Coin]) -> Result<(), ModelError> {
+        let amount = match funds {
+            [coin] if coin.denom == DENOM && !coin.amount.is_zero() => coin.amount.u128(),
+            _ => return Err(ModelError::Payment),
+        };
+        self.positions
+            .entry(sender.to_string())
+            .or_default()
+            .total_tokens += amount;
+        Ok(())
+    }
+
+    fn withdraw(&mut self, sender: &str, amount: u128) -> Result<Vec<BankMsg>, ModelError> {
+        let position = self.position(sender)?;
+        if amount > position.available() {
+            return Err(ModelError::InsufficientFunds);
+        }
+        position.total_tokens -= amount;
+
+        Ok(vec![BankMsg::Send {
+            to_address: sender.to_string(),
+            amount: vec![Coin::new(amount, DENOM)],
+        }])
+    }
+
+    fn stake(&mut self, sender: &str, amount: u128) -> Result<(), ModelError> {
+        let released_time = self.now + self.lock_period;
+        let position = self.position(sender)?;
+        if amount > position.available() {
+            return Err(ModelError::InsufficientFunds);
+        }
+        position.locked_tokens += amount;
+        position.voting_power += amount;
+        position.released_time = position.released_time.max(released_time);
+        Ok(())
+    }
+
+    fn unstake(&mut self, sender: &str, amount: u128) -> Result<(), ModelError> {
+        let now = self.now;
+        let position = self.position(sender)?;
+        if now < position.released_time {
+            return Err(ModelError::Unauthorized);
+        }
+        if position.locked_tokens < amount {
+            return Err(ModelError::InsufficientVotingPower);
+        }
+        position.voting_power -= position.voting_power_of(amount);
+        position.locked_tokens -= amount;
+        Ok(())
+    }
+
+    fn position(&mut self, user: &str) -> Result<&mut Position, ModelError> {
+        self.positions.get_mut(user).ok_or(ModelError::Std)
+    }
+
+    /// Moves `coins` like the bank module, which rejects transfers without any tokens
+    fn transfer(&mut self, from: &str, to: &str, coins: &[Coin]) -> Result<(), ModelError> {
+        let coins: Vec<_> = coins.iter().filter(|coin| !coin.amount.is_zero()).collect();
+        if coins.is_empty() {
+            return Err(ModelError::Bank);
+        }
+        for coin in coins {
+            let amount = coin.amount.u128();
+            let balance = self.balance(from, &coin.denom);
+            if balance < amount {
+                return Err(ModelError::Bank);
+            }
+            self.bank
+                .entry(from.to_string())
+                .or_default()
+                .insert(coin.denom.clone(), balance - amount);
+            self.mint(to, &coin.denom, amount);
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,412 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::Uint128;
+use cosmwasm_std::{
+    to_json_binary, Addr, Binary, Coin, CosmosMsg, StdResult, Timestamp, Uint128, WasmMsg,
+};
 
-use crate::state::UserInfo;
+use cw20::Cw20ReceiveMsg;
+
+use crate::state::{LockTier, UserInfo, VestingSchedule, WithdrawalLimit};
+
+#[cw_serde]
+#[derive(Default)]
+pub struct InstantiateMsg {
+    /// Address allowed to create vesting deposits, the instantiator if unset
+    pub owner: Option<String>,
+    /// Penalty charged on a fully locked early unstake, in basis points. The
+    /// remaining lock time is measured against the longest lock tier, if longer
+    /// than the lock period.
+    #[serde(default)]
+    pub early_unstake_penalty_bps: u64,
+    /// Receiver of early unstake penalties, remaining stakers if unset
+    pub treasury: Option<String>,
+    /// Whether unvested tokens can back voting power
+    #[serde(default)]
+    pub stake_unvested: bool,
+    /// Whether only allowlisted addresses can make and receive deposits, vesting
+    /// deposits by the owner aside
+    #[serde(default)]
+    pub allowlist_enabled: bool,
+    /// Most tokens a single user can hold, uncapped if unset
+    pub per_user_cap: Option<Uint128>,
+    /// Most tokens the contract can hold, uncapped if unset
+    pub global_cap: Option<Uint128>,
+    /// CW20 receipt token to instantiate, withdrawing without receipts if unset
+    pub receipt_token: Option<ReceiptTokenMsg>,
+    /// Cap on withdrawals over a sliding window, unlimited if unset
+    pub withdrawal_limit: Option<WithdrawalLimit>,
+}
 
 #[cw_serde]
-pub struct InstantiateMsg {}
+pub struct ReceiptTokenMsg {
+    /// Code of a cw20-base compatible contract
+    pub code_id: u64,
+    pub name: String,
+    pub symbol: String,
+}
 
 #[cw_serde]
 pub enum ExecuteMsg {
     Deposit {},
-    Withdraw { amount: Uint128 },
-    Stake { lock_amount: u128 },
-    Unstake { unlock_amount: u128 },
+    /// Deposits the attached funds into `recipient`'s position
+    DepositFor {
+        recipient: String,
+    },
+    /// Splits the attached funds across several positions
+    BatchDepositFor {
+        deposits: Vec<DepositFor>,
+    },
+    Withdraw {
+        amount: Uint128,
+    },
+    /// Withdraws tokens into `contract`, executing it with `msg` wrapped in a `WithdrawReceiveMsg`
+    WithdrawAndCall {
+        amount: Uint128,
+        contract: String,
+        msg: Binary,
+    },
+    /// Runs several operations in order, reverting all of them if one fails.
+    /// The attached funds must add up to the deposits.
+    Batch {
+        ops: Vec<BatchOp>,
+    },
+    /// Receipts sent back through CW20 `Send`, with a `ReceiptHookMsg` payload
+    Receive(Cw20ReceiveMsg),
+    /// Locks tokens for the base lock period, or for the chosen index in the lock tiers.
+    /// All locked tokens share the latest release time, and the voting power of a tier
+    /// is kept past it until the tokens are unstaked.
+    Stake {
+        lock_amount: u128,
+        tier: Option<u32>,
+    },
+    Unstake {
+        unlock_amount: u128,
+    },
+    /// Unstakes before the lock is released. With a receipt token, a non-zero penalty
+    /// has to be paid through `ReceiptHookMsg::EarlyUnstake` instead.
+    EarlyUnstake {
+        amount: u128,
+    },
+    /// Settles penalty shares and mints the receipts owed for them and for vested tokens
+    ClaimReceipts {},
+    /// Deposits the attached funds for `beneficiary`, vesting under `schedule`
+    CreateVestingDeposit {
+        beneficiary: String,
+        schedule: VestingSchedule,
+    },
+    /// Turns the deposit allowlist on or off
+    SetAllowlistEnabled {
+        enabled: bool,
+    },
+    /// Adds and removes allowlisted addresses
+    UpdateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Replaces the lock tiers and the minimum stake
+    UpdateLockTiers {
+        tiers: Vec<LockTier>,
+        min_stake: Uint128,
+    },
+    /// Replaces the deposit caps, removing those left unset
+    UpdateCaps {
+        per_user_cap: Option<Uint128>,
+        global_cap: Option<Uint128>,
+    },
+    /// Replaces the withdrawal limit, removing it if unset
+    UpdateWithdrawalLimit {
+        limit: Option<WithdrawalLimit>,
+    },
+    /// Blocks withdrawing, staking and unstaking for `user`
+    Freeze {
+        user: String,
+    },
+    Unfreeze {
+        user: String,
+    },
+}
+
+/// Upgrades a deployment from before settings and totals were kept in storage
+#[cw_serde]
+pub struct MigrateMsg {
+    /// Address allowed to create vesting deposits and administer the contract
+    pub owner: String,
+}
+
+/// Interventions reserved to chain governance
+#[cw_serde]
+pub enum SudoMsg {
+    /// Releases a user's lock immediately
+    ForceUnlock { user: String },
+    /// Changes the lock period applied to future stakes
+    SetLockPeriod { lock_period: u64 },
+    /// Suspends or resumes all user operations
+    SetPaused { paused: bool },
+    /// Sends all of a user's tokens back and clears their position. With a receipt token,
+    /// the tokens stay in the position, unlocked and vested, for receipt holders to withdraw.
+    ForceReturn { user: String },
+}
+
+/// Operation of a `Batch`, running the handler of the matching `ExecuteMsg`
+#[cw_serde]
+pub enum BatchOp {
+    Deposit {
+        amount: Uint128,
+    },
+    Withdraw {
+        amount: Uint128,
+    },
+    Stake {
+        lock_amount: u128,
+        tier: Option<u32>,
+    },
+    Unstake {
+        unlock_amount: u128,
+    },
+    EarlyUnstake {
+        amount: u128,
+    },
+}
+
+/// Operations paid for by returning receipts. Withdrawals burn them 1:1 with the withdrawn
+/// tokens and pay the holder, taking the tokens out of `owner`'s position, the holder's own
+/// by default. Another position only gives up tokens its owner no longer holds receipts for.
+#[cw_serde]
+pub enum ReceiptHookMsg {
+    Withdraw {
+        owner: Option<String>,
+    },
+    WithdrawAndCall {
+        contract: String,
+        msg: Binary,
+        owner: Option<String>,
+    },
+    /// Early unstakes from the holder's position, burning receipts for the penalty and
+    /// returning the rest
+    EarlyUnstake {
+        amount: u128,
+    },
+}
+
+/// Delivered to the receiving contract of `WithdrawAndCall` along with the tokens,
+/// like `Cw20ReceiveMsg` for CW20 `Send`
+#[cw_serde]
+pub struct WithdrawReceiveMsg {
+    /// User who withdrew the tokens
+    pub sender: String,
+    pub amount: Uint128,
+    /// Payload chosen by the user
+    pub msg: Binary,
+}
+
+impl WithdrawReceiveMsg {
+    /// Serializes the message wrapped in a `ReceiverExecuteMsg`
+    pub fn into_json_binary(self) -> StdResult<Binary> {
+        to_json_binary(&ReceiverExecuteMsg::Receive(self))
+    }
+
+    /// Executes `contract_addr` with the message, attaching `funds`
+    pub fn into_cosmos_msg(self, contract_addr: &Addr, funds: Vec<Coin>) -> StdResult<CosmosMsg> {
+        Ok(WasmMsg::Execute {
+            contract_addr: contract_addr.to_string(),
+            msg: self.into_json_binary()?,
+            funds,
+        }
+        .into())
+    }
+}
+
+/// Execute message receiving contracts must handle
+#[cw_serde]
+pub enum ReceiverExecuteMsg {
+    Receive(WithdrawReceiveMsg),
+}
+
+#[cw_serde]
+pub struct DepositFor {
+    pub recipient: String,
+    pub amount: Uint128,
 }
 
 #[cw_serde]
 #[derive(QueryResponses)]
 pub enum QueryMsg {
+    /// Zero values are returned for users without a position, unless `strict` is set
     #[returns(UserInfo)]
-    GetUser { user: String },
+    GetUser { user: String, strict: Option<bool> },
 
+    /// Zero is returned for users without a position, unless `strict` is set
     #[returns(u128)]
-    GetVotingPower { user: String },
+    GetVotingPower { user: String, strict: Option<bool> },
+
+    #[returns(EarlyUnstakePenaltyResponse)]
+    EarlyUnstakePenalty { user: String, amount: u128 },
+
+    #[returns(PositionResponse)]
+    Position { user: String },
+
+    #[returns(Option<VestingInfoResponse>)]
+    VestingInfo { user: String },
+
+    #[returns(LockTiersResponse)]
+    LockTiers {},
+
+    /// Remaining deposit capacity of the contract, and of `user` if given
+    #[returns(CapacityResponse)]
+    Capacity { user: Option<String> },
+
+    #[returns(AddressesResponse)]
+    Allowlist {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    #[returns(AddressesResponse)]
+    Frozen {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    #[returns(ReceiptTokenResponse)]
+    ReceiptToken {},
+
+    /// Withdrawals made within the current withdrawal window
+    #[returns(WithdrawalWindowResponse)]
+    WithdrawalWindow {},
+
+    /// Safety properties broken by the current state, see `crate::invariants`
+    #[cfg(feature = "invariants")]
+    #[returns(InvariantsResponse)]
+    CheckInvariants {},
+}
+
+#[cw_serde]
+pub struct EarlyUnstakePenaltyResponse {
+    /// Tokens that would be deducted from the user's position
+    pub penalty: Uint128,
+    /// Seconds left until the lock is released
+    pub remaining_lock: u64,
+}
+
+#[cw_serde]
+pub struct PositionResponse {
+    /// Total tokens deposited
+    pub total_tokens: Uint128,
+    /// User voting power
+    pub voting_power: u128,
+    /// Tokens that can be withdrawn right away, zero while paused or frozen and
+    /// at most what the withdrawal window has left
+    pub withdrawable: Uint128,
+    /// Tokens backing voting power
+    pub locked: Uint128,
+    /// Tokens that have not vested yet
+    pub unvested: Uint128,
+    /// Release time to unstake locked tokens
+    pub released_time: Timestamp,
+    /// Seconds left until the lock is released, relative to the current block
+    pub seconds_until_unlock: u64,
+    /// Whether `Unstake` would currently pass the release time check, false while
+    /// paused or frozen
+    pub can_unstake: bool,
+}
+
+#[cw_serde]
+pub struct VestingInfoResponse {
+    /// Tokens deposited under the schedule
+    pub amount: Uint128,
+    /// Tokens vested so far
+    pub vested: Uint128,
+    /// Tokens that have not vested yet
+    pub unvested: Uint128,
+    pub schedule: VestingSchedule,
+}
+
+#[cw_serde]
+pub struct AddressesResponse {
+    pub addresses: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct CapacityResponse {
+    /// Tokens the contract can still take in, `None` if uncapped
+    pub global: Option<Uint128>,
+    /// Tokens the user can still deposit under the per-user cap, `None` if uncapped
+    pub user: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct LockTiersResponse {
+    /// Base lock, granting one voting power per token
+    pub lock_period: u64,
+    pub tiers: Vec<LockTier>,
+    pub min_stake: Uint128,
+}
+
+/// Packets a counterparty sends over an IBC channel, each acknowledged with its response
+#[cw_serde]
+pub enum IbcPacketMsg {
+    /// Acknowledged with a `VotingPowerPacket`
+    VotingPower { user: String },
+    /// Acknowledged with a `TotalsPacket`
+    Totals {},
+    /// Pushes an `IbcUpdatePacket` with `user`'s voting power whenever it changes,
+    /// and with the totals whenever another user's does. Acknowledged with the
+    /// current voting power.
+    Subscribe { user: String },
+    /// Stops pushing updates for `user`, acknowledged with an empty response
+    Unsubscribe { user: String },
+}
+
+/// Voting power of a user, sent as an update or in an acknowledgement
+#[cw_serde]
+pub struct VotingPowerPacket {
+    pub user: String,
+    pub voting_power: u128,
+    /// Sum of the voting power of all users
+    pub total_voting_power: u128,
+}
+
+/// Update the contract pushes to channels with subscriptions
+#[cw_serde]
+pub struct IbcUpdatePacket {
+    pub update: IbcUpdate,
+    /// Times the update timed out and was sent again
+    pub retries: u32,
+}
+
+#[cw_serde]
+pub enum IbcUpdate {
+    /// Voting power of a subscribed user that changed
+    VotingPower(VotingPowerPacket),
+    /// Totals after the voting power of a user without a subscription changed
+    Totals(TotalsPacket),
+}
+
+#[cw_serde]
+pub struct TotalsPacket {
+    /// Tokens held for users
+    pub total_tokens: Uint128,
+    /// Sum of the voting power of all users
+    pub total_voting_power: u128,
+}
+
+#[cw_serde]
+pub struct ReceiptTokenResponse {
+    /// CW20 receipt token, `None` if withdrawals need no receipts
+    pub address: Option<Addr>,
+}
+
+#[cw_serde]
+pub struct WithdrawalWindowResponse {
+    pub limit: Option<WithdrawalLimit>,
+    /// Tokens withdrawn within the window
+    pub used: Uint128,
+    /// Tokens that can still be withdrawn within the window, `None` if unlimited
+    pub remaining: Option<Uint128>,
+}
+
+#[cfg(feature = "invariants")]
+#[cw_serde]
+pub struct InvariantsResponse {
+    /// Descriptions of the broken properties, empty if all hold
+    pub violations: Vec<String>,
 }
@@ -1,9 +1,11 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Timestamp, Uint128};
-use cw_storage_plus::Map;
+use cosmwasm_std::{Addr, Decimal, Empty, Timestamp, Uint128};
+use cw_storage_plus::{Item, Map};
+use serde::Deserialize;
 
 #[cw_serde]
 #[derive(Default)]
+#[serde(from = "StoredUserInfo")]
 pub struct UserInfo {
     /// Total tokens staked
     pub total_tokens: Uint128,
@@ -11,6 +13,220 @@ pub struct UserInfo {
     pub voting_power: u128,
     /// Release time to withdraw staked tokens
     pub released_time: Timestamp,
+    /// Tokens locked by staking, backing the voting power
+    pub locked_tokens: Uint128,
 }
 
+/// `UserInfo` as stored, positions saved before lock tiers having no locked tokens
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoredUserInfo {
+    total_tokens: Uint128,
+    voting_power: u128,
+    released_time: Timestamp,
+    locked_tokens: Option<Uint128>,
+}
+
+impl From<StoredUserInfo> for UserInfo {
+    fn from(stored: StoredUserInfo) -> Self {
+        UserInfo {
+            total_tokens: stored.total_tokens,
+            voting_power: stored.voting_power,
+            released_time: stored.released_time,
+            // without tiers every locked token backed one voting power
+            locked_tokens: stored
+                .locked_tokens
+                .unwrap_or(Uint128::new(stored.voting_power)),
+        }
+    }
+}
+
+impl UserInfo {
+    /// Tokens backing voting power, which cannot be withdrawn
+    pub fn locked(&self) -> Uint128 {
+        self.locked_tokens.min(self.total_tokens)
+    }
+
+    /// Share of the voting power backed by `amount` of the locked tokens
+    pub fn voting_power_of(&self, amount: Uint128) -> u128 {
+        if amount == self.locked_tokens {
+            self.voting_power
+        } else {
+            Uint128::new(self.voting_power)
+                .multiply_ratio(amount, self.locked_tokens)
+                .u128()
+        }
+    }
+
+    /// Tokens that can be withdrawn right away, keeping `unvested` tokens in place
+    pub fn withdrawable(&self, unvested: Uint128, stake_unvested: bool) -> Uint128 {
+        let restricted = if stake_unvested {
+            self.locked().max(unvested)
+        } else {
+            self.locked() + unvested
+        };
+        self.total_tokens.saturating_sub(restricted)
+    }
+
+    /// Tokens that can back additional voting power
+    pub fn stakeable(&self, unvested: Uint128, stake_unvested: bool) -> Uint128 {
+        let reserved = if stake_unvested {
+            Uint128::zero()
+        } else {
+            unvested
+        };
+        self.total_tokens.saturating_sub(self.locked() + reserved)
+    }
+
+    /// Whether the lock is released and voting power can be unstaked
+    pub fn is_unlocked(&self, now: Timestamp) -> bool {
+        now >= self.released_time
+    }
+
+    /// Seconds left until the lock is released
+    pub fn seconds_until_unlock(&self, now: Timestamp) -> u64 {
+        self.released_time.seconds().saturating_sub(now.seconds())
+    }
+}
+
+#[cw_serde]
+pub struct LockTier {
+    /// Seconds tokens stay locked
+    pub duration: u64,
+    /// Voting power granted per locked token
+    pub multiplier: Decimal,
+}
+
+#[cw_serde]
+pub struct WithdrawalLimit {
+    /// Share of the held tokens that can be withdrawn per window, in basis points
+    pub max_bps: u64,
+    /// Seconds the sliding window spans
+    pub window: u64,
+}
+
+#[cw_serde]
+pub struct VestingSchedule {
+    /// Time vesting starts
+    pub start_time: Timestamp,
+    /// Seconds after the start before anything vests
+    pub cliff: u64,
+    /// Seconds after the start until everything is vested
+    pub duration: u64,
+}
+
+impl VestingSchedule {
+    /// Portion of `amount` vested at `now`, linear after the cliff
+    pub fn vested(&self, amount: Uint128, now: Timestamp) -> Uint128 {
+        let elapsed = now.seconds().saturating_sub(self.start_time.seconds());
+        if now < self.start_time || elapsed < self.cliff {
+            Uint128::zero()
+        } else if elapsed >= self.duration {
+            amount
+        } else {
+            amount.multiply_ratio(elapsed, self.duration)
+        }
+    }
+}
+
+#[cw_serde]
+pub struct Vesting {
+    /// Tokens deposited under the schedule
+    pub amount: Uint128,
+    pub schedule: VestingSchedule,
+    /// Tokens receipts were minted for, as they vest
+    #[serde(default)]
+    pub receipted: Uint128,
+}
+
+impl Vesting {
+    /// Tokens that have not vested yet at `now`
+    pub fn unvested(&self, now: Timestamp) -> Uint128 {
+        self.amount - self.schedule.vested(self.amount, now)
+    }
+
+    /// Tokens no receipts were minted for yet
+    pub fn unreceipted(&self) -> Uint128 {
+        self.amount - self.receipted
+    }
+}
+
+#[cw_serde]
+pub struct Config {
+    /// Address allowed to create vesting deposits
+    pub owner: Addr,
+    /// Penalty charged on a fully locked early unstake, in basis points
+    pub early_unstake_penalty_bps: u64,
+    /// Receiver of early unstake penalties, remaining stakers if unset
+    pub treasury: Option<Addr>,
+    /// Seconds tokens stay locked after staking
+    pub lock_period: u64,
+    /// Whether user operations are suspended by chain governance
+    pub paused: bool,
+    /// Whether unvested tokens can back voting power
+    pub stake_unvested: bool,
+    /// Whether only allowlisted addresses can make and receive deposits
+    pub allowlist_enabled: bool,
+    /// Most tokens a single user can hold
+    pub per_user_cap: Option<Uint128>,
+    /// Most tokens the contract can hold
+    pub global_cap: Option<Uint128>,
+    /// Longer locks users can choose instead of `lock_period`
+    pub lock_tiers: Vec<LockTier>,
+    /// Fewest tokens a single stake can lock
+    pub min_stake: Uint128,
+    /// Cap on withdrawals over a sliding window
+    pub withdrawal_limit: Option<WithdrawalLimit>,
+}
+
+impl Config {
+    /// Longest lock a user can currently choose
+    pub fn max_lock_period(&self) -> u64 {
+        self.lock_tiers
+            .iter()
+            .map(|tier| tier.duration)
+            .fold(self.lock_period, u64::max)
+    }
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
 pub const VOTING_POWER: Map<&Addr, UserInfo> = Map::new("voting_power");
+
+pub const VESTING: Map<&Addr, Vesting> = Map::new("vesting");
+
+/// Addresses that can receive deposits while the allowlist is enabled
+pub const ALLOWLIST: Map<&Addr, Empty> = Map::new("allowlist");
+
+/// Addresses barred from withdrawing, staking and unstaking
+pub const FROZEN: Map<&Addr, Empty> = Map::new("frozen");
+
+/// Tokens held for users, including undistributed penalties
+pub const TOTAL_TOKENS: Item<Uint128> = Item::new("total_tokens");
+
+/// Sum of the voting power of all users
+pub const TOTAL_VOTING_POWER: Item<u128> = Item::new("total_voting_power");
+
+/// Early unstake penalties distributed per unit of voting power
+pub const PENALTY_INDEX: Item<Decimal> = Item::new("penalty_index");
+
+/// Value of `PENALTY_INDEX` when a user's share was last settled
+pub const USER_PENALTY_INDEX: Map<&Addr, Decimal> = Map::new("user_penalty_index");
+
+/// Penalties distributed to stakers but not yet settled into their positions
+pub const PENALTY_POOL: Item<Uint128> = Item::new("penalty_pool");
+
+/// Part of the pool the index rounded away, added to the next distribution
+pub const PENALTY_REMAINDER: Item<Uint128> = Item::new("penalty_remainder");
+
+/// Amount withdrawn per time bucket, keyed by the bucket's start in seconds
+pub const WITHDRAWALS: Map<u64, Uint128> = Map::new("withdrawals");
+
+/// CW20 token minted 1:1 on deposits, set once its instantiation replies
+pub const RECEIPT_TOKEN: Item<Addr> = Item::new("receipt_token");
+
+/// Connected IBC channels reporting voting power
+pub const IBC_CHANNELS: Map<&str, Empty> = Map::new("ibc_channels");
+
+/// Users whose voting power changes are pushed over a channel
+pub const IBC_SUBSCRIPTIONS: Map<(&str, &Addr), Empty> = Map::new("ibc_subscriptions");
@@ -1,3 +1,12 @@
+// Harness written against the ctf-02 Quint model, kept as written apart from
+// running it over the trace directory
+#![allow(
+    dead_code,
+    unused_imports,
+    unused_assignments,
+    clippy::assertions_on_constants
+)]
+
 mod state_structs {
 
     use num_bigint::BigInt;
@@ -99,14 +108,16 @@ mod contract_model_tests {
     use cosmwasm_std::{coin, StdError};
     use cosmwasm_std::{Addr, Uint128};
     use cw_multi_test::{App, ContractWrapper, Executor, AppResponse};
-    use itf::trace_from_str;
+    use oaksecurity_cosmwasm_ctf_02::mbt::{MbtError, TraceRunner};
     use serde::de::value::Error;
 
-    use crate::contract_model_test::state_structs::*;
-    use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-    use crate::contract::{DENOM, LOCK_PERIOD};
+    use crate::state_structs::*;
+    use oaksecurity_cosmwasm_ctf_02::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+    use oaksecurity_cosmwasm_ctf_02::contract::{DENOM, LOCK_PERIOD};
     use num_traits::ToPrimitive;
 
+    pub const TRACES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/quint/ctf-02/traces");
+
     // admin only instantiates the contract, no privileged role
     pub const ADMIN: &str = "admin";
 
@@ -128,8 +139,9 @@ mod contract_model_tests {
             // create a message to query the user info
             let msg = QueryMsg::GetUser {
                 user: user.clone(),
+                strict: None,
             };
-            let user_info: crate::state::UserInfo = app
+            let user_info: oaksecurity_cosmwasm_ctf_02::state::UserInfo = app
                 .wrap()
                 .query_wasm_smart(test_state.contract_addr.clone(), &msg)
                 .unwrap();
@@ -171,11 +183,16 @@ mod contract_model_tests {
 
     #[test]
     fn test_execute_itf(){
+        let summary = TraceRunner::new(TRACES_DIR).run(execute_itf).unwrap();
+        assert!(summary.is_success(), "{summary}");
+    }
+
+    fn execute_itf(trace: &itf::Trace<State>) -> Result<(), MbtError> {
         let mut app = App::default();
         let code = ContractWrapper::new(
-            crate::contract::execute,
-            crate::contract::instantiate,
-            crate::contract::query,
+            oaksecurity_cosmwasm_ctf_02::contract::execute,
+            oaksecurity_cosmwasm_ctf_02::contract::instantiate,
+            oaksecurity_cosmwasm_ctf_02::contract::query,
         );
         let code_id = app.store_code(Box::new(code));
 
@@ -184,11 +201,7 @@ mod contract_model_tests {
             contract_addr: Addr::unchecked(""),
         };
 
-        // now we can load trace data        
-        let data = include_str!("../../../quint/ctf-02/out.itf.json");
-        let trace: itf::Trace<State> = trace_from_str(data).unwrap();
-
-        for s in trace.states {
+        for s in &trace.states {
             let step_info = &s.value.step_info;
             print!("\n\n");
             // print debug info
@@ -196,7 +209,7 @@ mod contract_model_tests {
             match step_info.action_taken.as_str() {
                 "init" => {
                     println!("Initializing the contract");
-                    let msg = InstantiateMsg {};
+                    let msg = InstantiateMsg::default();
                     test_state.contract_addr = app
                         .instantiate_contract(
                             code_id, 
@@ -306,6 +319,7 @@ mod contract_model_tests {
                         println!("Staking {} tokens for {}", stake_args.stake_amount, stake_args.sender);
                         let msg = ExecuteMsg::Stake {
                             lock_amount: stake_args.stake_amount.to_u128().unwrap(),
+                            tier: None,
                         };
                         let res = app.execute_contract(
                             Addr::unchecked(stake_args.sender.clone()),
@@ -361,5 +375,6 @@ mod contract_model_tests {
         }
 
         assert!(true);
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,3 +1,12 @@
+// Harness written against the ctf-02 Quint model, kept as written apart from
+// running it over the trace directory
+#![allow(
+    dead_code,
+    unused_imports,
+    unused_parens,
+    clippy::assertions_on_constants
+)]
+
 mod state_structs {
     use num_bigint::BigInt;
     use serde::Deserialize;
@@ -93,13 +102,15 @@ mod model_tests_generated {
     use cosmwasm_std::{coin, BankMsg, CosmosMsg};
     use cosmwasm_std::{Addr, Uint128};
     use cw_multi_test::{App, ContractWrapper, Executor};
-    use itf::trace_from_str;
     use num_traits::ToPrimitive;
+    use oaksecurity_cosmwasm_ctf_02::mbt::{MbtError, TraceRunner};
+
+    use crate::state_structs::*;
+    use oaksecurity_cosmwasm_ctf_02::msg::*;
+    use oaksecurity_cosmwasm_ctf_02::ContractError;
 
-    use crate::contract_model_test_generated::state_structs::*;
-    use crate::msg::*;
-    use crate::ContractError;
-    use crate::integration_tests::tests::ADMIN;
+    pub const ADMIN: &str = "admin";
+    pub const TRACES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/quint/ctf-02/traces");
 
     pub const MAX_AMOUNT: u128 = 500;
     pub const NOERROR: &str = "no error";
@@ -134,12 +145,13 @@ mod model_tests_generated {
         // Compare voting power and user info for each user in the trace
         for (user, trace_user_info) in &state.system_state.voting_power {
             // Query the contract for user info
-            let user_info: crate::state::UserInfo = app
+            let user_info: oaksecurity_cosmwasm_ctf_02::state::UserInfo = app
                 .wrap()
                 .query_wasm_smart(
                     test_state.contract_addr.clone(),
                     &QueryMsg::GetUser {
                         user: user.clone(),
+                        strict: None,
                     },
                 )
                 .unwrap();
@@ -166,6 +178,7 @@ mod model_tests_generated {
                     test_state.contract_addr.clone(),
                     &QueryMsg::GetVotingPower {
                         user: user.clone(),
+                        strict: None,
                     },
                 )
                 .unwrap();
@@ -190,11 +203,18 @@ mod model_tests_generated {
 
     #[test]
     fn test_execute_json_generated() {
+        let summary = TraceRunner::new(TRACES_DIR)
+            .run(execute_json_generated)
+            .unwrap();
+        assert!(summary.is_success(), "{summary}");
+    }
+
+    fn execute_json_generated(trace: &itf::Trace<TraceState>) -> Result<(), MbtError> {
         let mut app = App::default();
         let code = ContractWrapper::new(
-            crate::contract::execute,
-            crate::contract::instantiate,
-            crate::contract::query,
+            oaksecurity_cosmwasm_ctf_02::contract::execute,
+            oaksecurity_cosmwasm_ctf_02::contract::instantiate,
+            oaksecurity_cosmwasm_ctf_02::contract::query,
         );
         let code_id = app.store_code(Box::new(code));
 
@@ -203,11 +223,7 @@ mod model_tests_generated {
             contract_addr: Addr::unchecked(""), // set in init step
         };
 
-        // load trace data
-        let data = include_str!("../../../quint/ctf-02/out.itf.json");
-        let trace: itf::Trace<TraceState> = trace_from_str(data).unwrap();
-
-        for s in trace.states {
+        for s in &trace.states {
             let step_info = &s.value.step_info;
             let sender = &step_info.msg_info.sender;
 
@@ -217,7 +233,7 @@ mod model_tests_generated {
                     println!("Initializing contract.");
 
                     // init contract
-                    let msg = InstantiateMsg {};
+                    let msg = InstantiateMsg::default();
                     test_state.contract_addr = app
                         .instantiate_contract(
                             code_id,
@@ -267,6 +283,7 @@ mod model_tests_generated {
                     // send the Stake message
                     let msg = ExecuteMsg::Stake {
                         lock_amount: amount.u128(),
+                        tier: None,
                     };
                     let res = app.execute_contract(
                         Addr::unchecked(sender),
@@ -426,5 +443,7 @@ mod model_tests_generated {
                 }
             }
         }
+
+        Ok(())
     }
 }
\ No newline at end of file
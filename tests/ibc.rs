@@ -0,0 +1,360 @@
+#[cfg(test)]
+pub mod tests {
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_ibc_channel_close_init, mock_ibc_channel_connect_ack,
+        mock_ibc_channel_open_init, mock_ibc_channel_open_try, mock_ibc_packet_ack,
+        mock_ibc_packet_recv, mock_ibc_packet_timeout, mock_info, MockApi, MockQuerier,
+        MockStorage,
+    };
+    use cosmwasm_std::{
+        coin, from_json, CosmosMsg, IbcAcknowledgement, IbcMsg, IbcOrder, OwnedDeps, StdAck,
+        Uint128,
+    };
+    use oaksecurity_cosmwasm_ctf_02::{
+        contract::{execute, instantiate, DENOM},
+        ibc::{
+            ibc_channel_close, ibc_channel_connect, ibc_channel_open, ibc_packet_ack,
+            ibc_packet_receive, ibc_packet_timeout, IBC_MAX_RETRIES, IBC_VERSION,
+        },
+        msg::{
+            ExecuteMsg, IbcPacketMsg, IbcUpdate, IbcUpdatePacket, InstantiateMsg, TotalsPacket,
+            VotingPowerPacket,
+        },
+        ContractError,
+    };
+
+    pub const USER: &str = "user";
+    pub const CHANNEL: &str = "channel-0";
+
+    type Deps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+    pub fn setup() -> Deps {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            InstantiateMsg::default(),
+        )
+        .unwrap();
+
+        ibc_channel_open(
+            deps.as_mut(),
+            mock_env(),
+            mock_ibc_channel_open_init(CHANNEL, IbcOrder::Unordered, IBC_VERSION),
+        )
+        .unwrap();
+        ibc_channel_connect(
+            deps.as_mut(),
+            mock_env(),
+            mock_ibc_channel_connect_ack(CHANNEL, IbcOrder::Unordered, IBC_VERSION),
+        )
+        .unwrap();
+        deps
+    }
+
+    pub fn receive(deps: &mut Deps, packet: &IbcPacketMsg) -> StdAck {
+        let msg = mock_ibc_packet_recv(CHANNEL, packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        from_json(res.acknowledgement).unwrap()
+    }
+
+    pub fn stake(deps: &mut Deps, amount: u128) -> Vec<CosmosMsg> {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(USER, &[coin(amount, DENOM)]),
+            ExecuteMsg::Deposit {},
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(USER, &[]),
+            ExecuteMsg::Stake {
+                lock_amount: amount,
+                tier: None,
+            },
+        )
+        .unwrap();
+        res.messages.into_iter().map(|msg| msg.msg).collect()
+    }
+
+    pub fn sent_update(msg: &CosmosMsg) -> (String, IbcUpdatePacket) {
+        match msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id, data, ..
+            }) => (channel_id.clone(), from_json(data).unwrap()),
+            msg => panic!("unexpected message {msg:?}"),
+        }
+    }
+
+    pub fn voting_power_update(packet: &IbcUpdatePacket) -> &VotingPowerPacket {
+        match &packet.update {
+            IbcUpdate::VotingPower(update) => update,
+            update => panic!("unexpected update {update:?}"),
+        }
+    }
+
+    pub fn subscribe(deps: &mut Deps, user: &str) {
+        receive(
+            deps,
+            &IbcPacketMsg::Subscribe {
+                user: user.to_string(),
+            },
+        );
+    }
+
+    #[test]
+    fn channel_handshake() {
+        let mut deps = mock_dependencies();
+
+        let err = ibc_channel_open(
+            deps.as_mut(),
+            mock_env(),
+            mock_ibc_channel_open_init(CHANNEL, IbcOrder::Ordered, IBC_VERSION),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::OrderedChannel {}));
+
+        let err = ibc_channel_open(
+            deps.as_mut(),
+            mock_env(),
+            mock_ibc_channel_open_try(CHANNEL, IbcOrder::Unordered, "ics20-1"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidIbcVersion { .. }));
+
+        ibc_channel_open(
+            deps.as_mut(),
+            mock_env(),
+            mock_ibc_channel_open_try(CHANNEL, IbcOrder::Unordered, IBC_VERSION),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn query_packets() {
+        let mut deps = setup();
+        stake(&mut deps, 300);
+
+        let ack = receive(
+            &mut deps,
+            &IbcPacketMsg::VotingPower {
+                user: USER.to_string(),
+            },
+        );
+        let voting_power: VotingPowerPacket = from_json(ack.unwrap()).unwrap();
+        assert_eq!(voting_power.voting_power, 300);
+        assert_eq!(voting_power.total_voting_power, 300);
+
+        // users without a position have no voting power
+        let ack = receive(
+            &mut deps,
+            &IbcPacketMsg::VotingPower {
+                user: "unknown".to_string(),
+            },
+        );
+        let voting_power: VotingPowerPacket = from_json(ack.unwrap()).unwrap();
+        assert_eq!(voting_power.voting_power, 0);
+
+        let ack = receive(&mut deps, &IbcPacketMsg::Totals {});
+        let totals: TotalsPacket = from_json(ack.unwrap()).unwrap();
+        assert_eq!(totals.total_tokens, Uint128::new(300));
+        assert_eq!(totals.total_voting_power, 300);
+
+        // malformed packets are acknowledged with an error
+        let msg = mock_ibc_packet_recv(CHANNEL, &"garbage").unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: StdAck = from_json(res.acknowledgement).unwrap();
+        assert!(!ack.is_success());
+    }
+
+    #[test]
+    fn subscriptions() {
+        let mut deps = setup();
+
+        // no updates are pushed without a subscription
+        assert!(stake(&mut deps, 100).is_empty());
+
+        let ack = receive(
+            &mut deps,
+            &IbcPacketMsg::Subscribe {
+                user: USER.to_string(),
+            },
+        );
+        let voting_power: VotingPowerPacket = from_json(ack.unwrap()).unwrap();
+        assert_eq!(voting_power.voting_power, 100);
+
+        let messages = stake(&mut deps, 50);
+        assert_eq!(messages.len(), 1);
+        let (channel_id, packet) = sent_update(&messages[0]);
+        assert_eq!(channel_id, CHANNEL);
+        assert_eq!(packet.retries, 0);
+        let update = voting_power_update(&packet);
+        assert_eq!(update.voting_power, 150);
+        assert_eq!(update.total_voting_power, 150);
+
+        // acks are recorded
+        let ack = mock_ibc_packet_ack(
+            CHANNEL,
+            &packet,
+            IbcAcknowledgement::new(StdAck::error("rejected")),
+        )
+        .unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), ack).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "success" && attr.value == "false"));
+
+        // timed out updates are sent again with the current voting power
+        let timeout = mock_ibc_packet_timeout(CHANNEL, &packet).unwrap();
+        stake(&mut deps, 25);
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout.clone()).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let (_, retry) = sent_update(&res.messages[0].msg);
+        assert_eq!(retry.retries, 1);
+        assert_eq!(voting_power_update(&retry).voting_power, 175);
+
+        receive(
+            &mut deps,
+            &IbcPacketMsg::Unsubscribe {
+                user: USER.to_string(),
+            },
+        );
+        assert!(stake(&mut deps, 25).is_empty());
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn closing_channel_drops_subscriptions() {
+        let mut deps = setup();
+        receive(
+            &mut deps,
+            &IbcPacketMsg::Subscribe {
+                user: USER.to_string(),
+            },
+        );
+        assert_eq!(stake(&mut deps, 100).len(), 1);
+
+        ibc_channel_close(
+            deps.as_mut(),
+            mock_env(),
+            mock_ibc_channel_close_init(CHANNEL, IbcOrder::Unordered, IBC_VERSION),
+        )
+        .unwrap();
+        assert!(stake(&mut deps, 100).is_empty());
+    }
+
+    #[test]
+    fn totals_pushed_to_other_subscribers() {
+        let mut deps = setup();
+        subscribe(&mut deps, "other");
+
+        // channels not subscribed to the staker still learn the new totals
+        let messages = stake(&mut deps, 100);
+        assert_eq!(messages.len(), 1);
+        let (channel_id, packet) = sent_update(&messages[0]);
+        assert_eq!(channel_id, CHANNEL);
+        assert_eq!(
+            packet.update,
+            IbcUpdate::Totals(TotalsPacket {
+                total_tokens: Uint128::new(100),
+                total_voting_power: 100,
+            })
+        );
+
+        // timed out totals are sent again while the channel has subscriptions
+        let timeout = mock_ibc_packet_timeout(CHANNEL, &packet).unwrap();
+        stake(&mut deps, 50);
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout.clone()).unwrap();
+        let (_, retry) = sent_update(&res.messages[0].msg);
+        assert_eq!(
+            retry.update,
+            IbcUpdate::Totals(TotalsPacket {
+                total_tokens: Uint128::new(150),
+                total_voting_power: 150,
+            })
+        );
+
+        receive(
+            &mut deps,
+            &IbcPacketMsg::Unsubscribe {
+                user: "other".to_string(),
+            },
+        );
+        assert!(stake(&mut deps, 25).is_empty());
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn totals_pushed_on_deposits_and_withdrawals() {
+        let mut deps = setup();
+        subscribe(&mut deps, "other");
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(USER, &[coin(100, DENOM)]),
+            ExecuteMsg::Deposit {},
+        )
+        .unwrap();
+        let (_, packet) = sent_update(&res.messages[0].msg);
+        assert_eq!(
+            packet.update,
+            IbcUpdate::Totals(TotalsPacket {
+                total_tokens: Uint128::new(100),
+                total_voting_power: 0,
+            })
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(USER, &[]),
+            ExecuteMsg::Withdraw {
+                amount: Uint128::new(40),
+            },
+        )
+        .unwrap();
+        let updates: Vec<_> = res
+            .messages
+            .iter()
+            .filter(|msg| matches!(msg.msg, CosmosMsg::Ibc(_)))
+            .map(|msg| sent_update(&msg.msg).1.update)
+            .collect();
+        assert_eq!(
+            updates,
+            vec![IbcUpdate::Totals(TotalsPacket {
+                total_tokens: Uint128::new(60),
+                total_voting_power: 0,
+            })]
+        );
+    }
+
+    #[test]
+    fn timed_out_updates_are_dropped_after_retries() {
+        let mut deps = setup();
+        subscribe(&mut deps, USER);
+
+        let messages = stake(&mut deps, 100);
+        let (_, mut packet) = sent_update(&messages[0]);
+        for retries in 1..=IBC_MAX_RETRIES {
+            let timeout = mock_ibc_packet_timeout(CHANNEL, &packet).unwrap();
+            let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap();
+            (_, packet) = sent_update(&res.messages[0].msg);
+            assert_eq!(packet.retries, retries);
+        }
+
+        let timeout = mock_ibc_packet_timeout(CHANNEL, &packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "retried" && attr.value == "false"));
+    }
+}
@@ -1,31 +1,62 @@
 #[cfg(test)]
 pub mod tests {
-    use crate::{
-        contract::{DENOM, LOCK_PERIOD},
-        msg::{ExecuteMsg, InstantiateMsg, QueryMsg},
-        state::UserInfo,
+    use cosmwasm_std::{
+        coin, from_json, to_json_binary, Addr, Binary, Decimal, Empty, Event, Response, StdError,
+        StdResult, Uint128,
     };
-    use cosmwasm_std::{coin, Addr, Empty, Uint128};
+    use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg, TokenInfoResponse};
     use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+    use oaksecurity_cosmwasm_ctf_02::{
+        contract::{DENOM, LOCK_PERIOD},
+        events::{
+            AdminChange, AdminEvent, BatchEvent, PenaltyShareEvent, PositionAction, PositionEvent,
+            PositionSnapshot, SudoEvent,
+        },
+        invariants,
+        msg::{
+            AddressesResponse, BatchOp, CapacityResponse, DepositFor, EarlyUnstakePenaltyResponse,
+            ExecuteMsg, InstantiateMsg, LockTiersResponse, MigrateMsg, PositionResponse, QueryMsg,
+            ReceiptHookMsg, ReceiptTokenMsg, ReceiptTokenResponse, ReceiverExecuteMsg, SudoMsg,
+            VestingInfoResponse, WithdrawalWindowResponse,
+        },
+        state::{LockTier, UserInfo, VestingSchedule, WithdrawalLimit, VOTING_POWER, WITHDRAWALS},
+    };
 
     pub fn challenge_contract() -> Box<dyn Contract<Empty>> {
         let contract = ContractWrapper::new(
-            crate::contract::execute,
-            crate::contract::instantiate,
-            crate::contract::query,
+            oaksecurity_cosmwasm_ctf_02::contract::execute,
+            oaksecurity_cosmwasm_ctf_02::contract::instantiate,
+            oaksecurity_cosmwasm_ctf_02::contract::query,
+        )
+        .with_sudo(oaksecurity_cosmwasm_ctf_02::contract::sudo)
+        .with_reply(oaksecurity_cosmwasm_ctf_02::contract::reply)
+        .with_migrate(oaksecurity_cosmwasm_ctf_02::contract::migrate);
+        Box::new(contract)
+    }
+
+    pub fn cw20_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            cw20_base::contract::execute,
+            cw20_base::contract::instantiate,
+            cw20_base::contract::query,
         );
         Box::new(contract)
     }
 
     pub const USER: &str = "user";
+    pub const USER2: &str = "user2";
     pub const ADMIN: &str = "admin";
+    pub const TREASURY: &str = "treasury";
 
     pub fn proper_instantiate() -> (App, Addr) {
+        instantiate_with(InstantiateMsg::default())
+    }
+
+    pub fn instantiate_with(msg: InstantiateMsg) -> (App, Addr) {
         let mut app = App::default();
         let cw_template_id = app.store_code(challenge_contract());
 
         // init contract
-        let msg = InstantiateMsg {};
         let contract_addr = app
             .instantiate_contract(
                 cw_template_id,
@@ -51,6 +82,25 @@ pub mod tests {
         app
     }
 
+    /// Contract accepting withdrawals, failing when asked to in the payload
+    pub fn receiver_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            |_deps, _env, info, msg: ReceiverExecuteMsg| -> StdResult<Response> {
+                let ReceiverExecuteMsg::Receive(receive) = msg;
+                if from_json::<String>(&receive.msg)? == "fail" {
+                    return Err(StdError::generic_err("receiver failed"));
+                }
+                Ok(Response::new()
+                    .add_attribute("sender", receive.sender)
+                    .add_attribute("amount", receive.amount)
+                    .add_attribute("funds", info.funds[0].amount))
+            },
+            |_deps, _env, _info, _msg: Empty| -> StdResult<Response> { Ok(Response::new()) },
+            |_deps, _env, _msg: Empty| -> StdResult<Binary> { Ok(Binary::default()) },
+        );
+        Box::new(contract)
+    }
+
     #[test]
     fn basic_flow() {
         let (mut app, contract_addr) = proper_instantiate();
@@ -77,6 +127,7 @@ pub mod tests {
         // query user
         let msg = QueryMsg::GetUser {
             user: (&USER).to_string(),
+            strict: None,
         };
         let user: UserInfo = app
             .wrap()
@@ -87,6 +138,7 @@ pub mod tests {
         // cannot stake more than deposited
         let msg = ExecuteMsg::Stake {
             lock_amount: amount.u128() + 1,
+            tier: None,
         };
         app.execute_contract(sender.clone(), contract_addr.clone(), &msg, &[])
             .unwrap_err();
@@ -94,6 +146,7 @@ pub mod tests {
         // normal stake
         let msg = ExecuteMsg::Stake {
             lock_amount: amount.u128(),
+            tier: None,
         };
         app.execute_contract(sender.clone(), contract_addr.clone(), &msg, &[])
             .unwrap();
@@ -101,6 +154,7 @@ pub mod tests {
         // query voting power
         let msg = QueryMsg::GetVotingPower {
             user: (&USER).to_string(),
+            strict: None,
         };
         let voting_power: u128 = app
             .wrap()
@@ -135,6 +189,7 @@ pub mod tests {
         // no more voting power
         let msg = QueryMsg::GetVotingPower {
             user: (&USER).to_string(),
+            strict: None,
         };
         let voting_power: u128 = app
             .wrap()
@@ -144,11 +199,2385 @@ pub mod tests {
 
         // normal withdraw
         let msg = ExecuteMsg::Withdraw { amount };
-        app.execute_contract(sender, contract_addr, &msg, &[])
+        app.execute_contract(sender, contract_addr.clone(), &msg, &[])
             .unwrap();
 
         // funds are received
         let balance = app.wrap().query_balance(USER, DENOM).unwrap().amount;
         assert_eq!(balance, amount);
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    pub fn deposit_and_stake(app: &mut App, contract_addr: &Addr, user: &str, amount: u128) {
+        let sender = Addr::unchecked(user);
+        app.execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(amount, DENOM)],
+        )
+        .unwrap();
+        app.execute_contract(
+            sender,
+            contract_addr.clone(),
+            &ExecuteMsg::Stake {
+                lock_amount: amount,
+                tier: None,
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn early_unstake_pays_treasury() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            early_unstake_penalty_bps: 1_000,
+            treasury: Some(TREASURY.to_string()),
+            ..Default::default()
+        });
+        let amount = 1_000_u128;
+        app = mint_tokens(app, USER.to_string(), Uint128::new(amount));
+        deposit_and_stake(&mut app, &contract_addr, USER, amount);
+
+        // half of the lock remains
+        app.update_block(|block| {
+            block.time = block.time.plus_seconds(LOCK_PERIOD / 2);
+        });
+
+        let preview: EarlyUnstakePenaltyResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::EarlyUnstakePenalty {
+                    user: USER.to_string(),
+                    amount,
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.penalty, Uint128::new(50));
+        assert_eq!(preview.remaining_lock, LOCK_PERIOD / 2);
+
+        // previews are limited to the locked tokens like the unstake itself
+        let err = app
+            .wrap()
+            .query_wasm_smart::<EarlyUnstakePenaltyResponse>(
+                contract_addr.clone(),
+                &QueryMsg::EarlyUnstakePenalty {
+                    user: USER.to_string(),
+                    amount: amount + 1,
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("Insufficient voting power"));
+
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::EarlyUnstake { amount },
+            &[],
+        )
+        .unwrap();
+
+        let balance = app.wrap().query_balance(TREASURY, DENOM).unwrap().amount;
+        assert_eq!(balance, preview.penalty);
+
+        // the remaining tokens are immediately withdrawable
+        let user: UserInfo = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::GetUser {
+                    user: USER.to_string(),
+                    strict: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(user.voting_power, 0);
+        assert_eq!(user.total_tokens, Uint128::new(950));
+
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: user.total_tokens,
+            },
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(USER, DENOM).unwrap().amount;
+        assert_eq!(balance, Uint128::new(950));
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn early_unstake_rewards_remaining_stakers() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            early_unstake_penalty_bps: 2_000,
+            ..Default::default()
+        });
+        let amount = 1_000_u128;
+        app = mint_tokens(app, USER.to_string(), Uint128::new(amount));
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(amount));
+        deposit_and_stake(&mut app, &contract_addr, USER, amount);
+        deposit_and_stake(&mut app, &contract_addr, USER2, amount);
+
+        // full penalty applies right after staking
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::EarlyUnstake { amount },
+            &[],
+        )
+        .unwrap();
+
+        let user: UserInfo = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::GetUser {
+                    user: USER.to_string(),
+                    strict: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(user.total_tokens, Uint128::new(800));
+
+        let user2: UserInfo = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::GetUser {
+                    user: USER2.to_string(),
+                    strict: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(user2.total_tokens, Uint128::new(1_200));
+
+        // the last staker has nobody to pay
+        let preview: EarlyUnstakePenaltyResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::EarlyUnstakePenalty {
+                    user: USER2.to_string(),
+                    amount,
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.penalty, Uint128::zero());
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn penalty_remainder_carried_forward() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            early_unstake_penalty_bps: 2_000,
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(2_000));
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(3));
+        deposit_and_stake(&mut app, &contract_addr, USER2, 3);
+        let early_unstake = |app: &mut App| {
+            deposit_and_stake(app, &contract_addr, USER, 1_000);
+            app.execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::EarlyUnstake { amount: 1_000 },
+                &[],
+            )
+            .unwrap();
+        };
+
+        // 200 split over 3 voting power leaves a token out of the index
+        early_unstake(&mut app);
+        let position = query_position(&app, &contract_addr, USER2);
+        assert_eq!(position.total_tokens, Uint128::new(3 + 199));
+
+        // which the next penalty distributes
+        early_unstake(&mut app);
+        let position = query_position(&app, &contract_addr, USER2);
+        assert_eq!(position.total_tokens, Uint128::new(3 + 400));
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn instantiate_msg_defaults() {
+        // payloads from before the penalty, vesting and allowlist options
+        let msg: InstantiateMsg = from_json(br#"{"owner":null}"#).unwrap();
+        assert_eq!(msg, InstantiateMsg::default());
+    }
+
+    #[test]
+    fn migrate_legacy_positions() {
+        // a deployment that only stored positions, without locked tokens
+        let legacy = ContractWrapper::new(
+            |_deps, _env, _info, _msg: Empty| -> StdResult<Response> { Ok(Response::new()) },
+            |deps, _env, _info, _msg: Empty| -> StdResult<Response> {
+                let key = VOTING_POWER.key(&Addr::unchecked(USER));
+                deps.storage.set(
+                    &key,
+                    br#"{"total_tokens":"1000","voting_power":"600","released_time":"0"}"#,
+                );
+                Ok(Response::new())
+            },
+            |_deps, _env, _msg: Empty| -> StdResult<Binary> { Ok(Binary::default()) },
+        );
+        let mut app = App::default();
+        let legacy_id = app.store_code(Box::new(legacy));
+        let code_id = app.store_code(challenge_contract());
+        let contract_addr = app
+            .instantiate_contract(
+                legacy_id,
+                Addr::unchecked(ADMIN),
+                &Empty {},
+                &[],
+                "legacy",
+                Some(ADMIN.to_string()),
+            )
+            .unwrap();
+        app = mint_tokens(app, contract_addr.to_string(), Uint128::new(1_000));
+
+        let msg = MigrateMsg {
+            owner: ADMIN.to_string(),
+        };
+        app.migrate_contract(Addr::unchecked(ADMIN), contract_addr.clone(), &msg, code_id)
+            .unwrap();
+        let user: UserInfo = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::GetUser {
+                    user: USER.to_string(),
+                    strict: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(user.total_tokens, Uint128::new(1_000));
+        assert_eq!(user.locked_tokens, Uint128::new(600));
+        assert_eq!(user.voting_power, 600);
+        let key = VOTING_POWER.key(&Addr::unchecked(USER));
+        let stored = app.wrap().query_wasm_raw(&contract_addr, &*key).unwrap();
+        let stored = String::from_utf8(stored.unwrap()).unwrap();
+        assert!(stored.contains(r#""locked_tokens":"600""#));
+        assert_invariants(&app, &contract_addr);
+
+        // migrating again leaves the state alone
+        app.migrate_contract(Addr::unchecked(ADMIN), contract_addr.clone(), &msg, code_id)
+            .unwrap();
+        app.update_block(|block| block.time = block.time.plus_seconds(LOCK_PERIOD));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Unstake { unlock_amount: 600 },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: Uint128::new(1_000),
+            },
+            &[],
+        )
+        .unwrap();
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn invalid_penalty_rejected() {
+        let mut app = App::default();
+        let code_id = app.store_code(challenge_contract());
+        app.instantiate_contract(
+            code_id,
+            Addr::unchecked(ADMIN),
+            &InstantiateMsg {
+                early_unstake_penalty_bps: 10_001,
+                ..Default::default()
+            },
+            &[],
+            "test",
+            None,
+        )
+        .unwrap_err();
+    }
+
+    /// Fails on any safety property of `invariants` broken by the contract
+    pub fn assert_invariants(app: &App, contract_addr: &Addr) {
+        let balance = app.wrap().query_balance(contract_addr, DENOM).unwrap();
+        let violations =
+            invariants::check_records(app.dump_wasm_raw(contract_addr), balance.amount).unwrap();
+        assert!(violations.is_empty(), "{violations:?}");
+    }
+
+    pub fn query_position(app: &App, contract_addr: &Addr, user: &str) -> PositionResponse {
+        assert_invariants(app, contract_addr);
+        app.wrap()
+            .query_wasm_smart(
+                contract_addr,
+                &QueryMsg::Position {
+                    user: user.to_string(),
+                },
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn position_tracks_lock() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+
+        // unknown users have an empty position
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::zero());
+        assert!(!position.can_unstake);
+
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Stake {
+                lock_amount: 400,
+                tier: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.withdrawable, Uint128::new(600));
+        assert_eq!(position.locked, Uint128::new(400));
+        assert_eq!(position.seconds_until_unlock, LOCK_PERIOD);
+        assert!(!position.can_unstake);
+
+        // withdrawing more than reported is rejected
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: position.withdrawable + Uint128::one(),
+            },
+            &[],
+        )
+        .unwrap_err();
+
+        app.update_block(|block| {
+            block.time = block.time.plus_seconds(LOCK_PERIOD);
+        });
+
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.seconds_until_unlock, 0);
+        assert!(position.can_unstake);
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Unstake { unlock_amount: 400 },
+            &[],
+        )
+        .unwrap();
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn unknown_and_invalid_users() {
+        let (app, contract_addr) = proper_instantiate();
+        let querier = app.wrap();
+
+        // unknown users have zero values by default
+        let user: UserInfo = querier
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::GetUser {
+                    user: USER.to_string(),
+                    strict: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(user, UserInfo::default());
+        let voting_power: u128 = querier
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::GetVotingPower {
+                    user: USER.to_string(),
+                    strict: Some(false),
+                },
+            )
+            .unwrap();
+        assert_eq!(voting_power, 0);
+
+        // unless strict mode is requested
+        let err = querier
+            .query_wasm_smart::<UserInfo>(
+                &contract_addr,
+                &QueryMsg::GetUser {
+                    user: USER.to_string(),
+                    strict: Some(true),
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("Gungnir user user not found"));
+
+        // invalid addresses report which address failed
+        let err = querier
+            .query_wasm_smart::<u128>(
+                &contract_addr,
+                &QueryMsg::GetVotingPower {
+                    user: "USER".to_string(),
+                    strict: None,
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid user address \"USER\""));
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn governance_sudo() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 1_000);
+
+        // pausing blocks user operations
+        let res = app
+            .wasm_sudo(contract_addr.clone(), &SudoMsg::SetPaused { paused: true })
+            .unwrap();
+        assert_eq!(
+            SudoEvent::parse_all(&res.events).unwrap(),
+            vec![SudoEvent::SetPaused { paused: true }]
+        );
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: Uint128::zero(),
+            },
+            &[],
+        )
+        .unwrap_err();
+        assert!(!query_position(&app, &contract_addr, USER).can_unstake);
+        app.wasm_sudo(contract_addr.clone(), &SudoMsg::SetPaused { paused: false })
+            .unwrap();
+
+        // force unlock allows unstaking before maturity
+        let res = app
+            .wasm_sudo(
+                contract_addr.clone(),
+                &SudoMsg::ForceUnlock {
+                    user: USER.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            SudoEvent::parse_all(&res.events).unwrap(),
+            vec![SudoEvent::ForceUnlock {
+                user: Addr::unchecked(USER)
+            }]
+        );
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Unstake { unlock_amount: 500 },
+            &[],
+        )
+        .unwrap();
+
+        // new stakes use the updated lock period
+        let res = app
+            .wasm_sudo(
+                contract_addr.clone(),
+                &SudoMsg::SetLockPeriod { lock_period: 60 },
+            )
+            .unwrap();
+        assert_eq!(
+            SudoEvent::parse_all(&res.events).unwrap(),
+            vec![SudoEvent::SetLockPeriod { lock_period: 60 }]
+        );
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Stake {
+                lock_amount: 100,
+                tier: None,
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            query_position(&app, &contract_addr, USER).seconds_until_unlock,
+            60
+        );
+
+        // force return pays out everything, including locked tokens
+        let res = app
+            .wasm_sudo(
+                contract_addr.clone(),
+                &SudoMsg::ForceReturn {
+                    user: USER.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            SudoEvent::parse_all(&res.events).unwrap(),
+            vec![SudoEvent::ForceReturn {
+                user: Addr::unchecked(USER),
+                amount: Uint128::new(1_000)
+            }]
+        );
+        let balance = app.wrap().query_balance(USER, DENOM).unwrap().amount;
+        assert_eq!(balance, Uint128::new(1_000));
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::zero());
+        assert_eq!(position.voting_power, 0);
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    pub fn create_vesting_deposit(
+        app: &mut App,
+        contract_addr: &Addr,
+        sender: &str,
+        amount: u128,
+        schedule: VestingSchedule,
+    ) -> anyhow::Result<()> {
+        app.execute_contract(
+            Addr::unchecked(sender),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateVestingDeposit {
+                beneficiary: USER.to_string(),
+                schedule,
+            },
+            &[coin(amount, DENOM)],
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn vesting_deposit() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, ADMIN.to_string(), Uint128::new(2_000));
+        let schedule = VestingSchedule {
+            start_time: app.block_info().time,
+            cliff: 100,
+            duration: 1_000,
+        };
+
+        // only the owner can create vesting deposits
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(1_000));
+        create_vesting_deposit(&mut app, &contract_addr, USER2, 1_000, schedule.clone())
+            .unwrap_err();
+
+        create_vesting_deposit(&mut app, &contract_addr, ADMIN, 1_000, schedule.clone()).unwrap();
+        create_vesting_deposit(&mut app, &contract_addr, ADMIN, 1_000, schedule.clone())
+            .unwrap_err();
+
+        // nothing can be withdrawn or staked before the cliff
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: Uint128::one(),
+            },
+            &[],
+        )
+        .unwrap_err();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Stake {
+                lock_amount: 1,
+                tier: None,
+            },
+            &[],
+        )
+        .unwrap_err();
+
+        // half vested
+        app.update_block(|block| {
+            block.time = block.time.plus_seconds(500);
+        });
+        let vesting: Option<VestingInfoResponse> = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::VestingInfo {
+                    user: USER.to_string(),
+                },
+            )
+            .unwrap();
+        let vesting = vesting.unwrap();
+        assert_eq!(vesting.vested, Uint128::new(500));
+        assert_eq!(vesting.unvested, Uint128::new(500));
+        assert_eq!(vesting.schedule, schedule);
+
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: Uint128::new(200),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Stake {
+                lock_amount: 300,
+                tier: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.unvested, Uint128::new(500));
+        assert_eq!(position.withdrawable, Uint128::zero());
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn vesting_deposit_can_stake_unvested() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            stake_unvested: true,
+            ..Default::default()
+        });
+        app = mint_tokens(app, ADMIN.to_string(), Uint128::new(1_000));
+        let schedule = VestingSchedule {
+            start_time: app.block_info().time,
+            cliff: 0,
+            duration: 1_000,
+        };
+        create_vesting_deposit(&mut app, &contract_addr, ADMIN, 1_000, schedule).unwrap();
+
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Stake {
+                lock_amount: 1_000,
+                tier: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        // vested tokens stay locked by the stake
+        app.update_block(|block| {
+            block.time = block.time.plus_seconds(1_000);
+        });
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.unvested, Uint128::zero());
+        assert_eq!(position.withdrawable, Uint128::zero());
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn deposit_for_recipients() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, ADMIN.to_string(), Uint128::new(1_000));
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked(ADMIN),
+                contract_addr.clone(),
+                &ExecuteMsg::DepositFor {
+                    recipient: USER.to_string(),
+                },
+                &[coin(100, DENOM)],
+            )
+            .unwrap();
+        assert!(res.has_event(
+            &Event::new("wasm-gungnir_deposit_for")
+                .add_attribute("sender", ADMIN)
+                .add_attribute("user", USER)
+                .add_attribute("amount", "100")
+        ));
+
+        // batch amounts must match the attached funds
+        let deposits = vec![
+            DepositFor {
+                recipient: USER.to_string(),
+                amount: Uint128::new(200),
+            },
+            DepositFor {
+                recipient: USER2.to_string(),
+                amount: Uint128::new(300),
+            },
+        ];
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::BatchDepositFor {
+                deposits: deposits.clone(),
+            },
+            &[coin(400, DENOM)],
+        )
+        .unwrap_err();
+
+        // and recipients must be valid addresses
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::BatchDepositFor {
+                deposits: vec![DepositFor {
+                    recipient: "USER".to_string(),
+                    amount: Uint128::new(500),
+                }],
+            },
+            &[coin(500, DENOM)],
+        )
+        .unwrap_err();
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked(ADMIN),
+                contract_addr.clone(),
+                &ExecuteMsg::BatchDepositFor { deposits },
+                &[coin(500, DENOM)],
+            )
+            .unwrap();
+        assert!(res.has_event(
+            &Event::new("wasm-gungnir_deposit_for")
+                .add_attribute("sender", ADMIN)
+                .add_attribute("user", USER2)
+                .add_attribute("amount", "300")
+        ));
+
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(300));
+        let position = query_position(&app, &contract_addr, USER2);
+        assert_eq!(position.total_tokens, Uint128::new(300));
+
+        // recipients own the deposits
+        app.execute_contract(
+            Addr::unchecked(USER2),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: Uint128::new(300),
+            },
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(USER2, DENOM).unwrap().amount;
+        assert_eq!(balance, Uint128::new(300));
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn deposit_allowlist() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            allowlist_enabled: true,
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        let deposit = |app: &mut App| {
+            app.execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Deposit {},
+                &[coin(100, DENOM)],
+            )
+        };
+        deposit(&mut app).unwrap_err();
+
+        // only the owner manages the allowlist
+        let msg = ExecuteMsg::UpdateAllowlist {
+            add: vec![USER.to_string(), USER2.to_string(), TREASURY.to_string()],
+            remove: vec![],
+        };
+        app.execute_contract(Addr::unchecked(USER), contract_addr.clone(), &msg, &[])
+            .unwrap_err();
+        app.execute_contract(Addr::unchecked(ADMIN), contract_addr.clone(), &msg, &[])
+            .unwrap();
+        deposit(&mut app).unwrap();
+
+        // third parties funding a deposit must be allowlisted too
+        app = mint_tokens(app, "outsider".to_string(), Uint128::new(200));
+        let msgs = [
+            ExecuteMsg::DepositFor {
+                recipient: USER.to_string(),
+            },
+            ExecuteMsg::BatchDepositFor {
+                deposits: vec![DepositFor {
+                    recipient: USER.to_string(),
+                    amount: Uint128::new(100),
+                }],
+            },
+        ];
+        for msg in &msgs {
+            let err = app
+                .execute_contract(
+                    Addr::unchecked("outsider"),
+                    contract_addr.clone(),
+                    msg,
+                    &[coin(100, DENOM)],
+                )
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                "outsider is not on the allowlist"
+            );
+        }
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &msgs[0],
+            &[coin(100, DENOM)],
+        )
+        .unwrap();
+
+        let page: AddressesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::Allowlist {
+                    start_after: None,
+                    limit: Some(2),
+                },
+            )
+            .unwrap();
+        assert_eq!(page.addresses, vec![TREASURY, USER]);
+        let page: AddressesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::Allowlist {
+                    start_after: Some(USER.to_string()),
+                    limit: Some(2),
+                },
+            )
+            .unwrap();
+        assert_eq!(page.addresses, vec![USER2]);
+
+        // disabling the allowlist opens deposits to everyone
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::UpdateAllowlist {
+                add: vec![],
+                remove: vec![USER.to_string()],
+            },
+            &[],
+        )
+        .unwrap();
+        deposit(&mut app).unwrap_err();
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::SetAllowlistEnabled { enabled: false },
+            &[],
+        )
+        .unwrap();
+        deposit(&mut app).unwrap();
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn frozen_positions() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 500);
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(500, DENOM)],
+        )
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::Freeze {
+                user: USER.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+        let frozen: AddressesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::Frozen {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(frozen.addresses, vec![USER]);
+
+        app.update_block(|block| {
+            block.time = block.time.plus_seconds(LOCK_PERIOD);
+        });
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.withdrawable, Uint128::zero());
+        assert!(!position.can_unstake);
+
+        // staking last, since it resets the lock
+        let frozen_msgs = [
+            ExecuteMsg::Unstake { unlock_amount: 100 },
+            ExecuteMsg::Withdraw {
+                amount: Uint128::new(100),
+            },
+            ExecuteMsg::Stake {
+                lock_amount: 100,
+                tier: None,
+            },
+        ];
+        for msg in &frozen_msgs {
+            app.execute_contract(Addr::unchecked(USER), contract_addr.clone(), msg, &[])
+                .unwrap_err();
+        }
+
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::Unfreeze {
+                user: USER.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.withdrawable, Uint128::new(500));
+        assert!(position.can_unstake);
+        for msg in &frozen_msgs {
+            app.execute_contract(Addr::unchecked(USER), contract_addr.clone(), msg, &[])
+                .unwrap();
+        }
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn deposit_caps() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            per_user_cap: Some(Uint128::new(500)),
+            global_cap: Some(Uint128::new(800)),
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(1_000));
+        let deposit = |app: &mut App, user: &str, amount: u128| {
+            app.execute_contract(
+                Addr::unchecked(user),
+                contract_addr.clone(),
+                &ExecuteMsg::Deposit {},
+                &[coin(amount, DENOM)],
+            )
+        };
+
+        deposit(&mut app, USER, 400).unwrap();
+        let err = deposit(&mut app, USER, 200).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Deposit cap exceeded, 100 can still be deposited"
+        );
+
+        deposit(&mut app, USER2, 400).unwrap();
+        let err = deposit(&mut app, USER2, 1).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Deposit cap exceeded, 0 can still be deposited"
+        );
+
+        let capacity: CapacityResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::Capacity {
+                    user: Some(USER.to_string()),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            capacity,
+            CapacityResponse {
+                global: Some(Uint128::zero()),
+                user: Some(Uint128::new(100)),
+            }
+        );
+
+        // withdrawals free up capacity
+        app.execute_contract(
+            Addr::unchecked(USER2),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: Uint128::new(100),
+            },
+            &[],
+        )
+        .unwrap();
+        deposit(&mut app, USER, 100).unwrap();
+
+        // caps can be lifted by the owner
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::UpdateCaps {
+                per_user_cap: None,
+                global_cap: None,
+            },
+            &[],
+        )
+        .unwrap();
+        deposit(&mut app, USER, 500).unwrap();
+        let capacity: CapacityResponse = app
+            .wrap()
+            .query_wasm_smart(contract_addr.clone(), &QueryMsg::Capacity { user: None })
+            .unwrap();
+        assert_eq!(
+            capacity,
+            CapacityResponse {
+                global: None,
+                user: None,
+            }
+        );
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn lock_tiers() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+
+        let tiers = vec![
+            LockTier {
+                duration: 30 * LOCK_PERIOD,
+                multiplier: Decimal::percent(150),
+            },
+            LockTier {
+                duration: 180 * LOCK_PERIOD,
+                multiplier: Decimal::percent(250),
+            },
+        ];
+        let msg = ExecuteMsg::UpdateLockTiers {
+            tiers: tiers.clone(),
+            min_stake: Uint128::new(100),
+        };
+        app.execute_contract(Addr::unchecked(USER), contract_addr.clone(), &msg, &[])
+            .unwrap_err();
+        app.execute_contract(Addr::unchecked(ADMIN), contract_addr.clone(), &msg, &[])
+            .unwrap();
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::UpdateLockTiers {
+                tiers: vec![LockTier {
+                    duration: LOCK_PERIOD,
+                    multiplier: Decimal::percent(50),
+                }],
+                min_stake: Uint128::zero(),
+            },
+            &[],
+        )
+        .unwrap_err();
+
+        let response: LockTiersResponse = app
+            .wrap()
+            .query_wasm_smart(contract_addr.clone(), &QueryMsg::LockTiers {})
+            .unwrap();
+        assert_eq!(response.tiers, tiers);
+
+        let stake = |app: &mut App, lock_amount: u128, tier: Option<u32>| {
+            app.execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Stake { lock_amount, tier },
+                &[],
+            )
+        };
+        let err = stake(&mut app, 50, Some(0)).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Stake must lock at least 100 tokens"
+        );
+        stake(&mut app, 200, Some(2)).unwrap_err();
+
+        stake(&mut app, 200, Some(0)).unwrap();
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.voting_power, 300);
+        assert_eq!(position.locked, Uint128::new(200));
+        assert_eq!(position.seconds_until_unlock, 30 * LOCK_PERIOD);
+
+        // the base lock adds voting power 1:1 without shortening the lock
+        stake(&mut app, 100, None).unwrap();
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.voting_power, 400);
+        assert_eq!(position.locked, Uint128::new(300));
+        assert_eq!(position.seconds_until_unlock, 30 * LOCK_PERIOD);
+
+        // unstaking releases voting power in proportion to the tokens unlocked
+        app.update_block(|block| {
+            block.time = block.time.plus_seconds(30 * LOCK_PERIOD);
+        });
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Unstake { unlock_amount: 150 },
+            &[],
+        )
+        .unwrap();
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.voting_power, 200);
+        assert_eq!(position.withdrawable, Uint128::new(850));
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn lock_tiers_share_the_position_lock() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            early_unstake_penalty_bps: 1_000,
+            treasury: Some(TREASURY.to_string()),
+            ..Default::default()
+        });
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::UpdateLockTiers {
+                tiers: vec![LockTier {
+                    duration: 4 * LOCK_PERIOD,
+                    multiplier: Decimal::percent(200),
+                }],
+                min_stake: Uint128::zero(),
+            },
+            &[],
+        )
+        .unwrap();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 400);
+        let stake = |app: &mut App, lock_amount: u128, tier: Option<u32>| {
+            app.execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Stake { lock_amount, tier },
+                &[],
+            )
+        };
+        let unstake = |app: &mut App, unlock_amount: u128| {
+            app.execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Unstake { unlock_amount },
+                &[],
+            )
+        };
+
+        // penalties scale against the longest tier, a quarter of it remaining here
+        let preview: EarlyUnstakePenaltyResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract_addr.clone(),
+                &QueryMsg::EarlyUnstakePenalty {
+                    user: USER.to_string(),
+                    amount: 400,
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.penalty, Uint128::new(10));
+
+        // a tier stake pushes back the release of the tokens already locked
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(100, DENOM)],
+        )
+        .unwrap();
+        stake(&mut app, 100, Some(0)).unwrap();
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.voting_power, 600);
+        assert_eq!(position.seconds_until_unlock, 4 * LOCK_PERIOD);
+        app.update_block(|block| block.time = block.time.plus_seconds(LOCK_PERIOD));
+        unstake(&mut app, 400).unwrap_err();
+
+        // the tier's voting power outlives its lock until the tokens are unstaked
+        app.update_block(|block| block.time = block.time.plus_seconds(3 * LOCK_PERIOD));
+        let position = query_position(&app, &contract_addr, USER);
+        assert!(position.can_unstake);
+        assert_eq!(position.voting_power, 600);
+        unstake(&mut app, 250).unwrap();
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.voting_power, 300);
+    }
+
+    #[test]
+    fn positions_stored_before_lock_tiers() {
+        // positions saved before lock tiers have no locked tokens, each locked token
+        // backing one voting power
+        let user: UserInfo = cosmwasm_std::from_json(
+            br#"{"total_tokens":"1000","voting_power":"400","released_time":"0"}"#,
+        )
+        .unwrap();
+        assert_eq!(user.locked_tokens, Uint128::new(400));
+        assert_eq!(user.withdrawable(Uint128::zero(), false), Uint128::new(600));
+        assert_eq!(user.voting_power_of(Uint128::new(100)), 100);
+
+        let user: UserInfo = cosmwasm_std::from_json(
+            br#"{"total_tokens":"1000","voting_power":"600","released_time":"0","locked_tokens":"300"}"#,
+        )
+        .unwrap();
+        assert_eq!(user.locked_tokens, Uint128::new(300));
+    }
+
+    #[test]
+    fn position_events() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Deposit {},
+                &[coin(1_000, DENOM)],
+            )
+            .unwrap();
+        let events = PositionEvent::parse_all(&res.events).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, PositionAction::Deposit);
+        assert_eq!(events[0].user, Addr::unchecked(USER));
+        assert_eq!(events[0].amount, Uint128::new(1_000));
+        assert_eq!(events[0].before, PositionSnapshot::default());
+        assert_eq!(events[0].after.total_tokens, Uint128::new(1_000));
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Stake {
+                    lock_amount: 400,
+                    tier: None,
+                },
+                &[],
+            )
+            .unwrap();
+        let events = PositionEvent::parse_all(&res.events).unwrap();
+        assert_eq!(events.len(), 1);
+        let stake = &events[0];
+        assert_eq!(stake.action, PositionAction::Stake);
+        assert_eq!(stake.before.voting_power, 0);
+        assert_eq!(stake.after.voting_power, 400);
+        assert_eq!(stake.after.locked_tokens, Uint128::new(400));
+        assert_eq!(
+            stake.after.released_time,
+            app.block_info().time.plus_seconds(LOCK_PERIOD)
+        );
+
+        // events round trip through the shared builder
+        assert_eq!(
+            PositionEvent::from_event(&stake.to_event()).unwrap(),
+            *stake
+        );
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Withdraw {
+                    amount: Uint128::new(600),
+                },
+                &[],
+            )
+            .unwrap();
+        assert!(res.has_event(
+            &Event::new("wasm-gungnir_withdraw")
+                .add_attribute("sender", USER)
+                .add_attribute("user", USER)
+                .add_attribute("amount", "600")
+                .add_attribute("total_tokens_before", "1000")
+                .add_attribute("total_tokens_after", "400")
+        ));
+
+        let res = app
+            .wasm_sudo(
+                contract_addr.clone(),
+                &SudoMsg::ForceReturn {
+                    user: USER.to_string(),
+                },
+            )
+            .unwrap();
+        let events = PositionEvent::parse_all(&res.events).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, PositionAction::ForceReturn);
+        assert_eq!(events[0].before.voting_power, 400);
+        assert_eq!(events[0].after, PositionSnapshot::default());
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn penalty_share_events() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            early_unstake_penalty_bps: 2_000,
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 1_000);
+        deposit_and_stake(&mut app, &contract_addr, USER2, 1_000);
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::EarlyUnstake { amount: 1_000 },
+                &[],
+            )
+            .unwrap();
+        assert!(PenaltyShareEvent::parse_all(&res.events)
+            .unwrap()
+            .is_empty());
+
+        // USER2's share is credited by their next operation
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER2),
+                contract_addr,
+                &ExecuteMsg::Withdraw {
+                    amount: Uint128::new(100),
+                },
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            PenaltyShareEvent::parse_all(&res.events).unwrap(),
+            vec![PenaltyShareEvent {
+                user: Addr::unchecked(USER2),
+                amount: Uint128::new(200),
+            }]
+        );
+
+        // the position event spans the settlement as well as the withdrawal
+        let events = PositionEvent::parse_all(&res.events).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].before.total_tokens, Uint128::new(1_000));
+        assert_eq!(events[0].after.total_tokens, Uint128::new(1_100));
+    }
+
+    #[test]
+    fn admin_events() {
+        let (mut app, contract_addr) = proper_instantiate();
+        let admin = Addr::unchecked(ADMIN);
+
+        let changes = [
+            (
+                ExecuteMsg::SetAllowlistEnabled { enabled: true },
+                AdminChange::SetAllowlistEnabled { enabled: true },
+            ),
+            (
+                ExecuteMsg::UpdateAllowlist {
+                    add: vec![USER.to_string(), USER2.to_string()],
+                    remove: vec![TREASURY.to_string()],
+                },
+                AdminChange::UpdateAllowlist {
+                    added: vec![Addr::unchecked(USER), Addr::unchecked(USER2)],
+                    removed: vec![Addr::unchecked(TREASURY)],
+                },
+            ),
+            (
+                ExecuteMsg::UpdateLockTiers {
+                    tiers: vec![LockTier {
+                        duration: LOCK_PERIOD * 2,
+                        multiplier: Decimal::percent(150),
+                    }],
+                    min_stake: Uint128::new(10),
+                },
+                AdminChange::UpdateLockTiers {
+                    tiers: vec![LockTier {
+                        duration: LOCK_PERIOD * 2,
+                        multiplier: Decimal::percent(150),
+                    }],
+                    min_stake: Uint128::new(10),
+                },
+            ),
+            (
+                ExecuteMsg::UpdateAllowlist {
+                    add: vec![],
+                    remove: vec![USER2.to_string()],
+                },
+                AdminChange::UpdateAllowlist {
+                    added: vec![],
+                    removed: vec![Addr::unchecked(USER2)],
+                },
+            ),
+            (
+                ExecuteMsg::UpdateCaps {
+                    per_user_cap: Some(Uint128::new(500)),
+                    global_cap: None,
+                },
+                AdminChange::UpdateCaps {
+                    per_user_cap: Some(Uint128::new(500)),
+                    global_cap: None,
+                },
+            ),
+            (
+                ExecuteMsg::UpdateWithdrawalLimit {
+                    limit: Some(WithdrawalLimit {
+                        max_bps: 2_500,
+                        window: 3_600,
+                    }),
+                },
+                AdminChange::UpdateWithdrawalLimit {
+                    limit: Some(WithdrawalLimit {
+                        max_bps: 2_500,
+                        window: 3_600,
+                    }),
+                },
+            ),
+            (
+                ExecuteMsg::UpdateWithdrawalLimit { limit: None },
+                AdminChange::UpdateWithdrawalLimit { limit: None },
+            ),
+            (
+                ExecuteMsg::Freeze {
+                    user: USER.to_string(),
+                },
+                AdminChange::Freeze {
+                    user: Addr::unchecked(USER),
+                },
+            ),
+            (
+                ExecuteMsg::Unfreeze {
+                    user: USER.to_string(),
+                },
+                AdminChange::Unfreeze {
+                    user: Addr::unchecked(USER),
+                },
+            ),
+        ];
+
+        for (msg, change) in changes {
+            let res = app
+                .execute_contract(admin.clone(), contract_addr.clone(), &msg, &[])
+                .unwrap();
+            let events = AdminEvent::parse_all(&res.events).unwrap();
+            assert_eq!(events, vec![AdminEvent::new(&admin, change)]);
+
+            // admin events are not mistaken for position events
+            assert!(PositionEvent::parse_all(&res.events).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn withdraw_and_call() {
+        let (mut app, contract_addr) = proper_instantiate();
+        let receiver_id = app.store_code(receiver_contract());
+        let receiver = app
+            .instantiate_contract(
+                receiver_id,
+                Addr::unchecked(ADMIN),
+                &Empty {},
+                &[],
+                "receiver",
+                None,
+            )
+            .unwrap();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::WithdrawAndCall {
+                    amount: Uint128::new(400),
+                    contract: receiver.to_string(),
+                    msg: to_json_binary("swap").unwrap(),
+                },
+                &[],
+            )
+            .unwrap();
+        assert!(res.has_event(
+            &Event::new("wasm")
+                .add_attribute("_contract_addr", receiver.as_str())
+                .add_attribute("sender", USER)
+                .add_attribute("amount", "400")
+                .add_attribute("funds", "400")
+        ));
+        let balance = app.wrap().query_balance(&receiver, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(400));
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(600));
+
+        // a failing receiver reverts the withdrawal
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::WithdrawAndCall {
+                amount: Uint128::new(100),
+                contract: receiver.to_string(),
+                msg: to_json_binary("fail").unwrap(),
+            },
+            &[],
+        )
+        .unwrap_err();
+        let balance = app.wrap().query_balance(&receiver, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(400));
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(600));
+
+        // the usual withdrawal limits apply
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::WithdrawAndCall {
+                amount: Uint128::new(700),
+                contract: receiver.to_string(),
+                msg: to_json_binary("swap").unwrap(),
+            },
+            &[],
+        )
+        .unwrap_err();
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    /// Instantiates the contract with a receipt token, returning both addresses
+    pub fn instantiate_with_receipts(msg: InstantiateMsg) -> (App, Addr, Addr) {
+        let mut app = App::default();
+        let code_id = app.store_code(challenge_contract());
+        let cw20_code_id = app.store_code(cw20_contract());
+        let contract_addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked(ADMIN),
+                &InstantiateMsg {
+                    receipt_token: Some(ReceiptTokenMsg {
+                        code_id: cw20_code_id,
+                        name: "Staked testcoin".to_string(),
+                        symbol: "stTEST".to_string(),
+                    }),
+                    ..msg
+                },
+                &[],
+                "test",
+                None,
+            )
+            .unwrap();
+
+        // the reply stored the receipt token address
+        let res: ReceiptTokenResponse = app
+            .wrap()
+            .query_wasm_smart(contract_addr.clone(), &QueryMsg::ReceiptToken {})
+            .unwrap();
+        (app, contract_addr, res.address.unwrap())
+    }
+
+    pub fn receipts(app: &App, receipt_token: &Addr, user: &str) -> Uint128 {
+        let res: BalanceResponse = app
+            .wrap()
+            .query_wasm_smart(
+                receipt_token,
+                &Cw20QueryMsg::Balance {
+                    address: user.to_string(),
+                },
+            )
+            .unwrap();
+        res.balance
+    }
+
+    /// Asserts receipts are minted for exactly the tokens held for users
+    pub fn assert_receipts_backed(app: &App, contract_addr: &Addr, receipt_token: &Addr) {
+        let info: TokenInfoResponse = app
+            .wrap()
+            .query_wasm_smart(receipt_token, &Cw20QueryMsg::TokenInfo {})
+            .unwrap();
+        let balance = app.wrap().query_balance(contract_addr, DENOM).unwrap();
+        assert_eq!(info.total_supply, balance.amount);
+    }
+
+    pub fn send_receipts(
+        app: &mut App,
+        contract_addr: &Addr,
+        receipt_token: &Addr,
+        sender: &str,
+        amount: u128,
+        msg: &ReceiptHookMsg,
+    ) -> anyhow::Result<cw_multi_test::AppResponse> {
+        app.execute_contract(
+            Addr::unchecked(sender),
+            receipt_token.clone(),
+            &Cw20ExecuteMsg::Send {
+                contract: contract_addr.to_string(),
+                amount: Uint128::new(amount),
+                msg: to_json_binary(msg).unwrap(),
+            },
+            &[],
+        )
+    }
+
+    #[test]
+    fn receipt_token() {
+        let (mut app, contract_addr, receipt_token) =
+            instantiate_with_receipts(InstantiateMsg::default());
+
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::new(1_000));
+
+        // withdrawals need receipts
+        let err = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Withdraw {
+                    amount: Uint128::new(100),
+                },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Withdrawals require returning receipt tokens"
+        );
+
+        // only the receipt token can deliver receipts
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: USER.to_string(),
+                amount: Uint128::new(100),
+                msg: to_json_binary(&ReceiptHookMsg::Withdraw { owner: None }).unwrap(),
+            }),
+            &[],
+        )
+        .unwrap_err();
+
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER,
+            400,
+            &ReceiptHookMsg::Withdraw { owner: None },
+        )
+        .unwrap();
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::new(600));
+        let balance = app.wrap().query_balance(USER, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(400));
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(600));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn transferred_receipts() {
+        let (mut app, contract_addr, receipt_token) =
+            instantiate_with_receipts(InstantiateMsg::default());
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            receipt_token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: USER2.to_string(),
+                amount: Uint128::new(400),
+            },
+            &[],
+        )
+        .unwrap();
+
+        // USER2 has no position of their own
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER2,
+            400,
+            &ReceiptHookMsg::Withdraw { owner: None },
+        )
+        .unwrap_err();
+
+        // the transferred receipts are redeemed against the depositor's position
+        let res = send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER2,
+            300,
+            &ReceiptHookMsg::Withdraw {
+                owner: Some(USER.to_string()),
+            },
+        )
+        .unwrap();
+        let events = PositionEvent::parse_all(&res.events).unwrap();
+        assert_eq!(events[0].sender, Addr::unchecked(USER2));
+        assert_eq!(events[0].user, Addr::unchecked(USER));
+        let balance = app.wrap().query_balance(USER2, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(300));
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(700));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+
+        // the tokens backing USER's own receipts stay theirs, even for USER2's own receipts
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(500));
+        app.execute_contract(
+            Addr::unchecked(USER2),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(500, DENOM)],
+        )
+        .unwrap();
+        let err = send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER2,
+            200,
+            &ReceiptHookMsg::Withdraw {
+                owner: Some(USER.to_string()),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Only 100 tokens of user's position are not backed by their receipts"
+        );
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER,
+            600,
+            &ReceiptHookMsg::Withdraw { owner: None },
+        )
+        .unwrap();
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER2,
+            100,
+            &ReceiptHookMsg::Withdraw {
+                owner: Some(USER.to_string()),
+            },
+        )
+        .unwrap();
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::zero());
+        assert_eq!(receipts(&app, &receipt_token, USER2), Uint128::new(500));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+    }
+
+    #[test]
+    fn frozen_receipt_holder() {
+        let (mut app, contract_addr, receipt_token) =
+            instantiate_with_receipts(InstantiateMsg::default());
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            receipt_token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: USER2.to_string(),
+                amount: Uint128::new(400),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::Freeze {
+                user: USER2.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+
+        // a frozen holder cannot cash out through someone else's position
+        let hooks = [
+            ReceiptHookMsg::Withdraw {
+                owner: Some(USER.to_string()),
+            },
+            ReceiptHookMsg::WithdrawAndCall {
+                contract: contract_addr.to_string(),
+                msg: Binary::default(),
+                owner: Some(USER.to_string()),
+            },
+        ];
+        for hook in hooks {
+            let err = send_receipts(&mut app, &contract_addr, &receipt_token, USER2, 400, &hook)
+                .unwrap_err();
+            assert_eq!(err.root_cause().to_string(), format!("{USER2} is frozen"));
+        }
+        assert_eq!(receipts(&app, &receipt_token, USER2), Uint128::new(400));
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(1_000));
+    }
+
+    #[test]
+    fn receipts_for_penalty_shares() {
+        let (mut app, contract_addr, receipt_token) = instantiate_with_receipts(InstantiateMsg {
+            early_unstake_penalty_bps: 2_000,
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 1_000);
+        deposit_and_stake(&mut app, &contract_addr, USER2, 1_000);
+
+        // the penalty leaves USER's position, so receipts must pay for it
+        let err = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::EarlyUnstake { amount: 1_000 },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Early unstaking requires returning 200 receipt tokens for the penalty"
+        );
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER,
+            250,
+            &ReceiptHookMsg::EarlyUnstake { amount: 1_000 },
+        )
+        .unwrap();
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::new(800));
+        assert_eq!(
+            query_position(&app, &contract_addr, USER).total_tokens,
+            Uint128::new(800)
+        );
+
+        // USER2's settled share comes with receipts to withdraw it
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER2),
+                contract_addr.clone(),
+                &ExecuteMsg::ClaimReceipts {},
+                &[],
+            )
+            .unwrap();
+        assert_eq!(PenaltyShareEvent::parse_all(&res.events).unwrap().len(), 1);
+        assert_eq!(receipts(&app, &receipt_token, USER2), Uint128::new(1_200));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER2,
+            200,
+            &ReceiptHookMsg::Withdraw { owner: None },
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(USER2, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(200));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+    }
+
+    #[test]
+    fn receipts_for_treasury_penalties() {
+        let (mut app, contract_addr, receipt_token) = instantiate_with_receipts(InstantiateMsg {
+            early_unstake_penalty_bps: 1_000,
+            treasury: Some(TREASURY.to_string()),
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 1_000);
+
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER,
+            100,
+            &ReceiptHookMsg::EarlyUnstake { amount: 1_000 },
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(TREASURY, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(100));
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::new(900));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+    }
+
+    #[test]
+    fn force_return_with_receipts() {
+        let (mut app, contract_addr, receipt_token) =
+            instantiate_with_receipts(InstantiateMsg::default());
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 1_000);
+        app.execute_contract(
+            Addr::unchecked(USER),
+            receipt_token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: USER2.to_string(),
+                amount: Uint128::new(1_000),
+            },
+            &[],
+        )
+        .unwrap();
+
+        // the tokens stay for the receipt holder instead of going back to USER
+        let res = app
+            .wasm_sudo(
+                contract_addr.clone(),
+                &SudoMsg::ForceReturn {
+                    user: USER.to_string(),
+                },
+            )
+            .unwrap();
+        let events = PositionEvent::parse_all(&res.events).unwrap();
+        assert_eq!(events[0].amount, Uint128::zero());
+        assert_eq!(events[0].after.voting_power, 0);
+        assert_eq!(events[0].after.total_tokens, Uint128::new(1_000));
+        let balance = app.wrap().query_balance(USER, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::zero());
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+
+        // and are withdrawable right away, despite the lock
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER2,
+            1_000,
+            &ReceiptHookMsg::Withdraw {
+                owner: Some(USER.to_string()),
+            },
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(USER2, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(1_000));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+    }
+
+    #[test]
+    fn receipts_for_vested_tokens() {
+        let (mut app, contract_addr, receipt_token) =
+            instantiate_with_receipts(InstantiateMsg::default());
+        app = mint_tokens(app, ADMIN.to_string(), Uint128::new(1_000));
+        let start_time = app.block_info().time;
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateVestingDeposit {
+                beneficiary: USER.to_string(),
+                schedule: VestingSchedule {
+                    start_time,
+                    cliff: 0,
+                    duration: 1_000,
+                },
+            },
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+
+        // nothing vested, nothing to redeem yet
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::zero());
+
+        app.update_block(|block| block.time = block.time.plus_seconds(250));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::ClaimReceipts {},
+            &[],
+        )
+        .unwrap();
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::new(250));
+
+        // vested tokens are redeemed while the next ones vest
+        app.update_block(|block| block.time = block.time.plus_seconds(250));
+        send_receipts(
+            &mut app,
+            &contract_addr,
+            &receipt_token,
+            USER,
+            250,
+            &ReceiptHookMsg::Withdraw { owner: None },
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::ClaimReceipts {},
+            &[],
+        )
+        .unwrap();
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::new(250));
+        assert_eq!(
+            query_position(&app, &contract_addr, USER).total_tokens,
+            Uint128::new(750)
+        );
+
+        // force returning mints the rest, as the tokens are released
+        app.wasm_sudo(
+            contract_addr.clone(),
+            &SudoMsg::ForceReturn {
+                user: USER.to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(receipts(&app, &receipt_token, USER), Uint128::new(750));
+        assert_receipts_backed(&app, &contract_addr, &receipt_token);
+    }
+
+    #[test]
+    fn withdrawal_limit() {
+        let limit = WithdrawalLimit {
+            max_bps: 1_000,
+            window: LOCK_PERIOD,
+        };
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            withdrawal_limit: Some(limit.clone()),
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+        let withdraw = |app: &mut App, amount: u128| {
+            app.execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Withdraw {
+                    amount: Uint128::new(amount),
+                },
+                &[],
+            )
+        };
+        let window = |app: &App| -> WithdrawalWindowResponse {
+            app.wrap()
+                .query_wasm_smart(contract_addr.clone(), &QueryMsg::WithdrawalWindow {})
+                .unwrap()
+        };
+
+        withdraw(&mut app, 60).unwrap();
+        withdraw(&mut app, 40).unwrap();
+        let err = withdraw(&mut app, 1).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Withdrawal limit of 1000 basis points per 86400 seconds exceeded, 0 can still be withdrawn"
+        );
+        assert_eq!(
+            window(&app),
+            WithdrawalWindowResponse {
+                limit: Some(limit),
+                used: Uint128::new(100),
+                remaining: Some(Uint128::zero()),
+            }
+        );
+
+        // withdrawals leave the window once it has passed
+        app.update_block(|block| block.time = block.time.plus_seconds(LOCK_PERIOD));
+        let res = window(&app);
+        assert_eq!(res.used, Uint128::zero());
+        assert_eq!(res.remaining, Some(Uint128::new(90)));
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.withdrawable, Uint128::new(90));
+        withdraw(&mut app, 90).unwrap();
+        withdraw(&mut app, 1).unwrap_err();
+
+        // only the owner can lift the limit
+        let msg = ExecuteMsg::UpdateWithdrawalLimit { limit: None };
+        app.execute_contract(Addr::unchecked(USER), contract_addr.clone(), &msg, &[])
+            .unwrap_err();
+        app.execute_contract(Addr::unchecked(ADMIN), contract_addr.clone(), &msg, &[])
+            .unwrap();
+        withdraw(&mut app, 810).unwrap();
+        assert_eq!(window(&app).remaining, None);
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn withdrawal_buckets() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            withdrawal_limit: Some(WithdrawalLimit {
+                max_bps: 1_000,
+                window: LOCK_PERIOD,
+            }),
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit {},
+            &[coin(1_000, DENOM)],
+        )
+        .unwrap();
+        let withdraw = |app: &mut App, amount: u128| {
+            app.execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Withdraw {
+                    amount: Uint128::new(amount),
+                },
+                &[],
+            )
+            .unwrap();
+        };
+        let bucket = |app: &App, start: u64| {
+            WITHDRAWALS
+                .query(&app.wrap(), contract_addr.clone(), start)
+                .unwrap()
+        };
+
+        // withdrawals within the same hour share a bucket
+        let now = app.block_info().time.seconds();
+        let first = now - now % (LOCK_PERIOD / 24);
+        withdraw(&mut app, 10);
+        withdraw(&mut app, 5);
+        assert_eq!(bucket(&app, first), Some(Uint128::new(15)));
+
+        // later withdrawals clear buckets that left the window
+        app.update_block(|block| block.time = block.time.plus_seconds(LOCK_PERIOD));
+        withdraw(&mut app, 10);
+        assert_eq!(bucket(&app, first), None);
+        let window: WithdrawalWindowResponse = app
+            .wrap()
+            .query_wasm_smart(contract_addr.clone(), &QueryMsg::WithdrawalWindow {})
+            .unwrap();
+        assert_eq!(window.used, Uint128::new(10));
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn batch() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+
+        // attached funds must match the deposits
+        let ops = vec![BatchOp::Deposit {
+            amount: Uint128::new(500),
+        }];
+        let err = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Batch { ops },
+                &[coin(1_000, DENOM)],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Deposits add up to 500 but 1000 was attached"
+        );
+
+        let ops = vec![
+            BatchOp::Deposit {
+                amount: Uint128::new(1_000),
+            },
+            BatchOp::Stake {
+                lock_amount: 600,
+                tier: None,
+            },
+            BatchOp::Withdraw {
+                amount: Uint128::new(400),
+            },
+        ];
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Batch { ops },
+                &[coin(1_000, DENOM)],
+            )
+            .unwrap();
+        let actions: Vec<_> = PositionEvent::parse_all(&res.events)
+            .unwrap()
+            .into_iter()
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                PositionAction::Deposit,
+                PositionAction::Stake,
+                PositionAction::Withdraw
+            ]
+        );
+        let ops: Vec<_> = BatchEvent::parse_all(&res.events)
+            .unwrap()
+            .into_iter()
+            .map(|op| (op.index, op.attribute("action").unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (0, "deposit".to_string()),
+                (1, "stake".to_string()),
+                (2, "withdraw".to_string())
+            ]
+        );
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(600));
+        assert_eq!(position.voting_power, 600);
+        let balance = app.wrap().query_balance(USER, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(400));
+
+        // a failing op reverts the whole batch and is named in the error
+        let ops = vec![
+            BatchOp::Deposit {
+                amount: Uint128::new(400),
+            },
+            BatchOp::Unstake { unlock_amount: 600 },
+        ];
+        let err = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                contract_addr.clone(),
+                &ExecuteMsg::Batch { ops },
+                &[coin(400, DENOM)],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Batch op 1 failed: Unauthorized"
+        );
+        let position = query_position(&app, &contract_addr, USER);
+        assert_eq!(position.total_tokens, Uint128::new(600));
+        let balance = app.wrap().query_balance(USER, DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(400));
+
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn invariants_hold_across_penalties() {
+        let (mut app, contract_addr) = instantiate_with(InstantiateMsg {
+            early_unstake_penalty_bps: 2_000,
+            ..Default::default()
+        });
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        app = mint_tokens(app, USER2.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 1_000);
+        deposit_and_stake(&mut app, &contract_addr, USER2, 1_000);
+        assert_invariants(&app, &contract_addr);
+
+        // the penalty moves into the pool before USER2 settles it
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::EarlyUnstake { amount: 1_000 },
+            &[],
+        )
+        .unwrap();
+        assert_invariants(&app, &contract_addr);
+
+        app.execute_contract(
+            Addr::unchecked(USER),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {
+                amount: Uint128::new(800),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER2),
+            contract_addr.clone(),
+            &ExecuteMsg::EarlyUnstake { amount: 1_000 },
+            &[],
+        )
+        .unwrap();
+        assert_invariants(&app, &contract_addr);
+    }
+
+    #[test]
+    fn invariants_detect_missing_funds() {
+        let (mut app, contract_addr) = proper_instantiate();
+        app = mint_tokens(app, USER.to_string(), Uint128::new(1_000));
+        deposit_and_stake(&mut app, &contract_addr, USER, 1_000);
+
+        // tokens sent outside of a deposit are a surplus, not a violation
+        app = mint_tokens(app, contract_addr.to_string(), Uint128::new(5));
+        assert_invariants(&app, &contract_addr);
+
+        app.send_tokens(
+            contract_addr.clone(),
+            Addr::unchecked(USER2),
+            &[coin(15, DENOM)],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(&contract_addr, DENOM).unwrap();
+        let violations =
+            invariants::check_records(app.dump_wasm_raw(&contract_addr), balance.amount).unwrap();
+        assert_eq!(
+            violations,
+            vec![invariants::InvariantViolation::Balance {
+                balance: Uint128::new(990),
+                owed: Uint128::new(1_000),
+            }]
+        );
+
+        #[cfg(feature = "invariants")]
+        {
+            let response: oaksecurity_cosmwasm_ctf_02::msg::InvariantsResponse = app
+                .wrap()
+                .query_wasm_smart(&contract_addr, &QueryMsg::CheckInvariants {})
+                .unwrap();
+            assert_eq!(
+                response.violations,
+                vec!["contract holds 990testcoin but owes 1000testcoin"]
+            );
+        }
     }
 }
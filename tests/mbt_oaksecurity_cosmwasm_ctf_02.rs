@@ -1,334 +1,278 @@
-
-pub mod state_structs {
-    use num_bigint::BigInt;
-    use serde::Deserialize;
-    use std::collections::HashMap;
-    use itf::de::{self, As};
-    
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct InstantiateMsg {
-        
-    }
-
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct UserInfo {
-        pub total_tokens: BigInt,
-        pub voting_power: BigInt,
-        pub released_time: BigInt
-    }
-    
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct ContractState {
-        pub voting_power: HashMap<String, UserInfo>
-    }
-    
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct NondetPicks {
-        
-        #[serde(with = "As::<de::Option::<_>>")]
-        pub sender: Option<String>,
-        
-        #[serde(with = "As::<de::Option::<_>>")]
-        pub denom: Option<String>,
-        
-        #[serde(with = "As::<de::Option::<_>>")]
-        pub amount: Option<BigInt>,
-        
-        #[serde(with = "As::<de::Option::<_>>")]
-        pub message_amount: Option<BigInt>,
-        
-        #[serde(with = "As::<de::Option::<_>>")]
-        pub message_lock_amount: Option<BigInt>,
-        
-        #[serde(with = "As::<de::Option::<_>>")]
-        pub message_unlock_amount: Option<BigInt>
-    }
-    
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct Message {}
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct Attribute {
-        pub key: String,
-        pub value: QuintSerializedValue,
+#[cfg(test)]
+pub mod tests {
+    use cosmwasm_std::Uint128;
+    use oaksecurity_cosmwasm_ctf_02::mbt::trace::{BankMsg, CosmosMsg, NondetPicks, State};
+    use std::fs;
+
+    use oaksecurity_cosmwasm_ctf_02::mbt::shrink::{load_raw_trace, write_trace};
+    use oaksecurity_cosmwasm_ctf_02::mbt::{
+        load_trace, shrink, ActionRegistry, ErrorTable, MbtError, Replay, ReplayConfig,
+        TimeMapping, TraceRunner,
+    };
+    use oaksecurity_cosmwasm_ctf_02::msg::ExecuteMsg;
+    use oaksecurity_cosmwasm_ctf_02::ContractError;
+
+    pub fn gungnir_actions() -> ActionRegistry {
+        ActionRegistry::new()
+            .register("deposit_action", |_| Ok(ExecuteMsg::Deposit {}))
+            .register("withdraw_action", |picks| {
+                Ok(ExecuteMsg::Withdraw {
+                    amount: Uint128::new(NondetPicks::number(
+                        &picks.message_amount,
+                        "message_amount",
+                    )?),
+                })
+            })
+            .register("stake_action", |picks| {
+                Ok(ExecuteMsg::Stake {
+                    lock_amount: NondetPicks::number(
+                        &picks.message_lock_amount,
+                        "message_lock_amount",
+                    )?,
+                    tier: None,
+                })
+            })
+            .register("unstake_action", |picks| {
+                Ok(ExecuteMsg::Unstake {
+                    unlock_amount: NondetPicks::number(
+                        &picks.message_unlock_amount,
+                        "message_unlock_amount",
+                    )?,
+                })
+            })
     }
 
-    #[derive(Clone, Debug, Deserialize)]
-    #[serde(tag = "tag", content = "value")]
-    pub enum QuintSerializedValue {
-        FromInt(BigInt),
-        FromStr(String),
-        FromListInt(Vec<BigInt>),
-    }
+    /// Traces of the stub model, locking for a day of model time
+    pub const TRACES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/quint/traces/stub");
+    /// Traces of a model locking for two ticks of eight hours
+    pub const LOCK_EXPIRY_TRACES_DIR: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/quint/traces/lock_expiry");
 
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct Response {
-        pub messages: Vec<Message>,
-        pub attributes: Vec<Attribute>,
+    pub fn trace() -> itf::Trace<State> {
+        load_trace(format!("{TRACES_DIR}/test.itf.json")).unwrap()
     }
 
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct State {
-        pub contract_state: ContractState,
-        pub bank: HashMap<String, HashMap<String, BigInt>>,
-        #[serde(with = "As::<de::Result::<_, _>>")]
-        pub result: Result<Response, String>,
-        pub action_taken: String,
-        pub nondet_picks: NondetPicks,
-        pub time: BigInt,
+    #[test]
+    fn model_test() {
+        let replay = Replay::new(gungnir_actions());
+        let summary = TraceRunner::new(TRACES_DIR)
+            .run(|trace| replay.run(trace))
+            .unwrap();
+        assert!(summary.is_success(), "{summary}");
     }
 
-}
-    
-#[cfg(test)]
-pub mod tests {
-    use oaksecurity_cosmwasm_ctf_02::contract;
-    use oaksecurity_cosmwasm_ctf_02::msg::{ExecuteMsg, InstantiateMsg};
-
-
-    use crate::state_structs::*;
-    use cosmwasm_std::{coin, Addr, Uint128};
-    use cw_multi_test::{App, AppResponse, ContractWrapper, Executor};
-    use itf::trace_from_str;
-    use num_bigint::BigInt;
-    use num_traits::{ToPrimitive, Zero};
-
-    pub const DENOM: &str = "uawesome";
-    pub const TICK: u64 = 1;
+    #[test]
+    fn message_processing() {
+        // the withdrawal of step 4 pays out 40 to sender1 once its message is processed
+        let mut trace = trace();
+        let Ok(response) = &mut trace.states[4].value.result else {
+            panic!("step 4 should succeed");
+        };
+        let CosmosMsg::Bank(BankMsg::Send { to_address, .. }) = &mut response.messages[0].msg
+        else {
+            panic!("step 4 should send funds");
+        };
+        *to_address = "sender2".to_string();
 
-    pub fn mint_tokens(mut app: App, recipient: String, denom: String, amount: Uint128) -> App {
-        app.sudo(cw_multi_test::SudoMsg::Bank(
-            cw_multi_test::BankSudo::Mint {
-                to_address: recipient.to_owned(),
-                amount: vec![coin(amount.u128(), denom)],
-            },
-        ))
-        .unwrap();
-        app
+        let err = Replay::new(gungnir_actions()).run(&trace).unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 5, .. }));
+        let err = err.to_string();
+        assert!(err.contains("send 40testcoin to sender2"));
+        assert!(err.contains("send 40testcoin to sender1"));
     }
 
-    fn compare_state(test_state: &TestState, app: &App, state: &State) {
-        // compare contract balances
-        let balance = app
-            .wrap()
-            .query_balance(&test_state.contract_addr, DENOM)
-            .unwrap()
-            .amount;
-        let trace_balance = state
-            .bank
-            .get(&test_state.contract_addr.to_string())
-            .and_then(|x| x.get(DENOM))
-            .and_then(|x| x.to_u128())
-            .unwrap_or(0);
-        println!(
-            "Contract balance ({:?}) for {DENOM}: {:?} vs {:?}",
-            test_state.contract_addr,
-            balance,
-            Uint128::new(trace_balance)
-        );
-        assert_eq!(balance, Uint128::new(trace_balance));
-
-        // TODO: Query the contract and compare the state as you wish
+    #[test]
+    fn lock_expiry() {
+        let config = ReplayConfig {
+            time: TimeMapping {
+                seconds_per_tick: 8 * 60 * 60,
+                blocks_per_tick: 5760,
+            },
+            lock_period: Some(2),
+            ..ReplayConfig::default()
+        };
+        let replay = Replay::with_config(gungnir_actions(), config);
+        let summary = TraceRunner::new(LOCK_EXPIRY_TRACES_DIR)
+            .run(|trace| replay.run(trace))
+            .unwrap();
+        assert!(summary.is_success(), "{summary}");
+
+        // release times no longer match once the lock period is off by a tick
+        let config = ReplayConfig {
+            time: TimeMapping {
+                seconds_per_tick: 8 * 60 * 60,
+                blocks_per_tick: 5760,
+            },
+            lock_period: Some(3),
+            ..ReplayConfig::default()
+        };
+        let trace = load_trace(format!("{LOCK_EXPIRY_TRACES_DIR}/unlock.itf.json")).unwrap();
+        let err = Replay::with_config(gungnir_actions(), config)
+            .run(&trace)
+            .unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 2, .. }));
+        assert!(err.to_string().contains("sender1.released_time"));
     }
 
-    fn compare_result(
-        trace_result: Result<Response, String>,
-        app_result: Result<AppResponse, anyhow::Error>,
-    ) {
-        if trace_result.is_ok() {
-            assert!(
-                app_result.is_ok(),
-                "Action unexpectedly failed, error: {:?}",
-                app_result.err()
-            );
-            println!("Action successful as expected");
-        } else {
-            assert!(
-                app_result.is_err(),
-                "Expected action to fail with error: {:?}",
-                trace_result.err()
-            );
-            println!("Action failed as expected");
-        }
+    #[test]
+    fn contract_only_positions() {
+        // the model forgets the position opened by the deposit of step 1
+        let mut trace = trace();
+        trace.states[1].value.contract_state.voting_power.clear();
+
+        let err = Replay::new(gungnir_actions()).run(&trace).unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 1, .. }));
+        assert!(err.to_string().contains(".position  false  true"));
     }
 
-    fn funds_from_trace(amount: Option<BigInt>, denom: Option<String>) -> Vec<cosmwasm_std::Coin> {
-        if amount.is_none() || denom.is_none() || amount == Some(Zero::zero()) {
-            return vec![];
-        }
+    #[test]
+    fn reference_model() {
+        let replay = Replay::new(gungnir_actions());
+        let summary = TraceRunner::new(TRACES_DIR)
+            .run(|trace| replay.check_model(trace))
+            .unwrap();
+        assert!(summary.is_success(), "{summary}");
+
+        let config = ReplayConfig {
+            lock_period: Some(2),
+            ..ReplayConfig::default()
+        };
+        let replay = Replay::with_config(gungnir_actions(), config);
+        let summary = TraceRunner::new(LOCK_EXPIRY_TRACES_DIR)
+            .run(|trace| replay.check_model(trace))
+            .unwrap();
+        assert!(summary.is_success(), "{summary}");
+
+        // the reference model pays the withdrawal of step 4 to sender1
+        let mut trace = trace();
+        let Ok(response) = &mut trace.states[4].value.result else {
+            panic!("step 4 should succeed");
+        };
+        let CosmosMsg::Bank(BankMsg::Send { to_address, .. }) = &mut response.messages[0].msg
+        else {
+            panic!("step 4 should send funds");
+        };
+        *to_address = "sender2".to_string();
 
-        vec![coin(
-            amount.as_ref().unwrap().to_u128().unwrap(),
-            denom.unwrap(),
-        )]
+        let err = Replay::new(gungnir_actions())
+            .check_model(&trace)
+            .unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 5, .. }));
+        assert!(err.to_string().contains("send 40testcoin to sender1"));
     }
 
-    // Testing is stateful.
-    struct TestState {
-        // we will only know the contract address once we have processed an `instantiate` step
-        pub contract_addr: Addr,
+    #[test]
+    fn shrink_diverging_trace() {
+        // the withdrawal of step 4 pays out to sender2 in the model
+        let mut raw = load_raw_trace(format!("{TRACES_DIR}/test.itf.json")).unwrap();
+        raw.states[4].value["result"]["value"]["messages"][0]["msg"]["value"]["value"]
+            ["to_address"] = "sender2".into();
+        let replay = Replay::new(gungnir_actions());
+        let shrunk = shrink(&raw, |trace| replay.run(trace)).unwrap();
+
+        // the failed withdrawal and the stake are dropped along with the steps after the
+        // divergence, leaving the deposit and the withdrawal it pays out
+        let actions: Vec<_> = shrunk
+            .trace
+            .states
+            .iter()
+            .map(|state| state.value["action_taken"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                "q::init",
+                "deposit_action",
+                "withdraw_action",
+                "withdraw_action"
+            ]
+        );
+        assert!(matches!(
+            shrunk.divergence,
+            MbtError::Divergence { step: 3, .. }
+        ));
+        assert!(shrunk
+            .divergence
+            .to_string()
+            .contains("send 40testcoin to sender2"));
+
+        // the regression written by the runner reproduces the divergence on its own
+        let dir = std::env::temp_dir().join(format!("mbt-shrink-{}", std::process::id()));
+        write_trace(dir.join("traces/diverging.itf.json"), &raw).unwrap();
+        let summary = TraceRunner::new(dir.join("traces"))
+            .filter(None)
+            .shrink_into(Some(dir.join("shrunk")))
+            .run(|trace| replay.run(trace))
+            .unwrap();
+        assert!(summary.failed[0].1.contains("shrunk 9 states to 4"));
+        let regression = load_trace(dir.join("shrunk/diverging.itf.json")).unwrap();
+        let err = replay.run(&regression).unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 3, .. }));
+        fs::remove_dir_all(dir).unwrap();
     }
 
     #[test]
-    fn model_test() {
-        let mut app = App::default();
-        let code = ContractWrapper::new(contract::execute, contract::instantiate, contract::query);
-        let code_id = app.store_code(Box::new(code));
+    fn trace_runner() {
+        let replay = Replay::new(gungnir_actions());
+        let summary = TraceRunner::new(TRACES_DIR)
+            .filter(Some("no-such-trace".to_string()))
+            .run(|trace| replay.run(trace))
+            .unwrap();
+        assert!(summary.passed.is_empty());
+        assert_eq!(summary.filtered, 1);
+
+        // failing traces are reported instead of aborting the run
+        let summary = TraceRunner::new(TRACES_DIR)
+            .filter(Some("test".to_string()))
+            .run(|_: &itf::Trace<State>| panic!("replay failed"))
+            .unwrap();
+        assert!(!summary.is_success());
+        assert_eq!(summary.failed[0].0, "test.itf.json");
+        assert!(summary.failed[0].1.contains("replay failed"));
+
+        let err = TraceRunner::new(format!("{TRACES_DIR}/missing"))
+            .run(|trace| replay.run(trace))
+            .unwrap_err();
+        assert!(matches!(err, MbtError::Load { .. }));
+    }
 
-        // create test state
-        let mut test_state = TestState {
-            contract_addr: Addr::unchecked("contract0"),
+    #[test]
+    fn error_variants() {
+        let replay = |errors: ErrorTable| {
+            let config = ReplayConfig {
+                errors,
+                ..ReplayConfig::default()
+            };
+            Replay::with_config(gungnir_actions(), config).run(&trace())
         };
 
-        // load trace data
-        let data = include_str!("../quint/test.itf.json");
-        let trace: itf::Trace<State> = trace_from_str(data).unwrap();
-
-        for s in trace.states {
-            let last_result = s.value.result.clone();
-            if last_result.is_ok() && !last_result.unwrap().messages.is_empty() {
-                println!("Processing messages, skipping");
-                continue;
-            }
-
-            let action_taken = &s.value.action_taken;
-            let nondet_picks = &s.value.nondet_picks;
-            let amount = nondet_picks.amount.clone();
-            let denom = nondet_picks.denom.clone();
-            let sender = nondet_picks.sender.clone();
-
-            println!("Step number: {:?}", s.meta.index);
-            println!("Result from trace: {:?}", s.value.result.clone());
-
-            match action_taken.as_str() {
-
-
-               "deposit_action" => {
-                    let sender = Addr::unchecked(sender.unwrap());
-                    let funds = funds_from_trace(amount, denom);
-
-                    let msg = ExecuteMsg::Deposit {  };
-                    println!("Message: {:?}", msg);
-                    println!("Sender: {:?}", sender);
-                    println!("Funds: {:?}", funds);
-
-                    let res = app.execute_contract(
-                        sender,
-                        test_state.contract_addr.clone(),
-                        &msg,
-                        &funds,
-                    );
-
-                    compare_result(s.value.result.clone(), res)
-                }
-
-
-               "q::init" => {
-                    println!("Initializing contract.");
-
-                    let sender = Addr::unchecked(sender.unwrap());
-                    let funds = funds_from_trace(amount, denom);
-
-                    let msg = InstantiateMsg {  };
-                    println!("Message: {:?}", msg);
-                    println!("Sender: {:?}", sender);
-                    println!("Funds: {:?}", funds);
-
-                    test_state.contract_addr = app.instantiate_contract(
-                        code_id,
-                        sender,
-                        &msg,
-                        &funds,
-                        "test",
-                        None,
-                    ).unwrap();
-
-                    for (addr, coins) in s.value.bank.clone().iter() {
-                        for (denom, amount) in coins.iter() {
-                            app = mint_tokens(
-                                app,
-                                addr.clone(),
-                                denom.to_string(),
-                                Uint128::new(amount.to_u128().unwrap()),
-                            );
-                        }
-                    }
-
-               }
-
-
-               "stake_action" => {
-                    let sender = Addr::unchecked(sender.unwrap());
-                    let funds = funds_from_trace(amount, denom);
-
-                    let message_lock_amount = nondet_picks.message_lock_amount.clone().unwrap().to_u64().unwrap().into();
-                    let msg = ExecuteMsg::Stake { lock_amount: message_lock_amount };
-                    println!("Message: {:?}", msg);
-                    println!("Sender: {:?}", sender);
-                    println!("Funds: {:?}", funds);
-
-                    let res = app.execute_contract(
-                        sender,
-                        test_state.contract_addr.clone(),
-                        &msg,
-                        &funds,
-                    );
-
-                    compare_result(s.value.result.clone(), res)
-                }
-
-
-               "unstake_action" => {
-                    let sender = Addr::unchecked(sender.unwrap());
-                    let funds = funds_from_trace(amount, denom);
-
-                    let message_unlock_amount = nondet_picks.message_unlock_amount.clone().unwrap().to_u64().unwrap().into();
-                    let msg = ExecuteMsg::Unstake { unlock_amount: message_unlock_amount };
-                    println!("Message: {:?}", msg);
-                    println!("Sender: {:?}", sender);
-                    println!("Funds: {:?}", funds);
-
-                    let res = app.execute_contract(
-                        sender,
-                        test_state.contract_addr.clone(),
-                        &msg,
-                        &funds,
-                    );
-
-                    compare_result(s.value.result.clone(), res)
-                }
-
-
-               "withdraw_action" => {
-                    let sender = Addr::unchecked(sender.unwrap());
-                    let funds = funds_from_trace(amount, denom);
-
-                    let message_amount = nondet_picks.message_amount.clone().unwrap().to_u64().unwrap().into();
-                    let msg = ExecuteMsg::Withdraw { amount: message_amount };
-                    println!("Message: {:?}", msg);
-                    println!("Sender: {:?}", sender);
-                    println!("Funds: {:?}", funds);
-
-                    let res = app.execute_contract(
-                        sender,
-                        test_state.contract_addr.clone(),
-                        &msg,
-                        &funds,
-                    );
-
-                    compare_result(s.value.result.clone(), res)
-                }
-
-                _ => panic!("Invalid action taken"),
-            }
-            compare_state(&test_state, &app, &(s.value.clone()));
-            println!(
-                "clock is advancing for {} seconds",
-                TICK
-            );
-            app.update_block(|block| {
-                block.time = block.time.plus_seconds(TICK);
-            });
-            println!("-----------------------------------");
-        }
+        replay(ReplayConfig::default().errors).unwrap();
+        // unknown model errors are only accepted outside strict mode
+        replay(ErrorTable::new()).unwrap();
+        let err = replay(ErrorTable::new().strict(true)).unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 3, .. }));
+        assert!(err
+            .to_string()
+            .contains("no matching ContractError variant"));
+
+        let err = replay(ErrorTable::gungnir().map("InsufficientFunds", |err| {
+            matches!(err, ContractError::Unauthorized {})
+        }))
+        .unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 3, .. }));
+        assert!(err.to_string().contains("InsufficientFunds"));
+
+        // the reference model is held to the same table
+        let config = ReplayConfig {
+            errors: ErrorTable::new().strict(true),
+            ..ReplayConfig::default()
+        };
+        let err = Replay::with_config(gungnir_actions(), config)
+            .check_model(&trace())
+            .unwrap_err();
+        assert!(matches!(err, MbtError::Divergence { step: 3, .. }));
+        assert!(err
+            .to_string()
+            .contains("no matching ContractError variant"));
     }
 }
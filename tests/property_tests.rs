@@ -0,0 +1,282 @@
+#[cfg(test)]
+pub mod tests {
+    use cosmwasm_std::{coin, Addr, Coin, Empty, Uint128};
+    use cw_multi_test::{App, BankSudo, Contract, ContractWrapper, Executor, SudoMsg};
+    use oaksecurity_cosmwasm_ctf_02::{
+        contract::{DENOM, LOCK_PERIOD},
+        invariants,
+        model::Model,
+        msg::{ExecuteMsg, InstantiateMsg, QueryMsg},
+        state::UserInfo,
+    };
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+
+    pub const USERS: [&str; 3] = ["alice", "bob", "carol"];
+    pub const OTHER_DENOM: &str = "other";
+    pub const INITIAL_BALANCE: u128 = 500;
+
+    #[derive(Clone, Debug)]
+    pub enum Op {
+        Deposit {
+            sender: &'static str,
+            denom: &'static str,
+            amount: u128,
+        },
+        Withdraw {
+            sender: &'static str,
+            amount: u128,
+        },
+        Stake {
+            sender: &'static str,
+            amount: u128,
+        },
+        Unstake {
+            sender: &'static str,
+            amount: u128,
+        },
+        AdvanceTime {
+            seconds: u64,
+        },
+    }
+
+    pub fn op() -> impl Strategy<Value = Op> {
+        let sender = || prop::sample::select(USERS.to_vec());
+        let amount = || 0..=INITIAL_BALANCE + 100;
+        prop_oneof![
+            (
+                sender(),
+                prop::sample::select(vec![DENOM, OTHER_DENOM]),
+                amount()
+            )
+                .prop_map(|(sender, denom, amount)| Op::Deposit {
+                    sender,
+                    denom,
+                    amount
+                }),
+            (sender(), amount()).prop_map(|(sender, amount)| Op::Withdraw { sender, amount }),
+            (sender(), amount()).prop_map(|(sender, amount)| Op::Stake { sender, amount }),
+            (sender(), amount()).prop_map(|(sender, amount)| Op::Unstake { sender, amount }),
+            (0..=2 * LOCK_PERIOD).prop_map(|seconds| Op::AdvanceTime { seconds }),
+        ]
+    }
+
+    impl Op {
+        /// Sender, message and funds of the op, `None` for time jumps
+        pub fn message(&self) -> Option<(&'static str, ExecuteMsg, Vec<Coin>)> {
+            match *self {
+                Op::Deposit {
+                    sender,
+                    denom,
+                    amount,
+                } => {
+                    let funds = if amount == 0 {
+                        vec![]
+                    } else {
+                        vec![coin(amount, denom)]
+                    };
+                    Some((sender, ExecuteMsg::Deposit {}, funds))
+                }
+                Op::Withdraw { sender, amount } => Some((
+                    sender,
+                    ExecuteMsg::Withdraw {
+                        amount: Uint128::new(amount),
+                    },
+                    vec![],
+                )),
+                Op::Stake { sender, amount } => Some((
+                    sender,
+                    ExecuteMsg::Stake {
+                        lock_amount: amount,
+                        tier: None,
+                    },
+                    vec![],
+                )),
+                Op::Unstake { sender, amount } => Some((
+                    sender,
+                    ExecuteMsg::Unstake {
+                        unlock_amount: amount,
+                    },
+                    vec![],
+                )),
+                Op::AdvanceTime { .. } => None,
+            }
+        }
+    }
+
+    pub fn challenge_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            oaksecurity_cosmwasm_ctf_02::contract::execute,
+            oaksecurity_cosmwasm_ctf_02::contract::instantiate,
+            oaksecurity_cosmwasm_ctf_02::contract::query,
+        );
+        Box::new(contract)
+    }
+
+    pub fn setup() -> (App, Addr) {
+        let mut app = App::default();
+        let code_id = app.store_code(challenge_contract());
+        let contract_addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("admin"),
+                &InstantiateMsg::default(),
+                &[],
+                "test",
+                None,
+            )
+            .unwrap();
+        for user in USERS {
+            app.sudo(SudoMsg::Bank(BankSudo::Mint {
+                to_address: user.to_string(),
+                amount: vec![
+                    coin(INITIAL_BALANCE, DENOM),
+                    coin(INITIAL_BALANCE, OTHER_DENOM),
+                ],
+            }))
+            .unwrap();
+        }
+        (app, contract_addr)
+    }
+
+    /// Executes `op` on the chain, returning whether the contract accepted it
+    pub fn execute(app: &mut App, contract_addr: &Addr, op: &Op) -> bool {
+        if let Op::AdvanceTime { seconds } = *op {
+            app.update_block(|block| block.time = block.time.plus_seconds(seconds));
+            return true;
+        }
+        let (sender, msg, funds) = op.message().expect("only time jumps have no message");
+        app.execute_contract(Addr::unchecked(sender), contract_addr.clone(), &msg, &funds)
+            .is_ok()
+    }
+
+    /// Applies `op` to the model, returning whether the contract should accept it
+    pub fn apply(model: &mut Model, op: &Op) -> bool {
+        if let Op::AdvanceTime { seconds } = *op {
+            model.advance_time(seconds);
+            return true;
+        }
+        let (sender, msg, funds) = op.message().expect("only time jumps have no message");
+        model.execute(sender, &msg, &funds).is_ok()
+    }
+
+    /// Compares positions and balances with the model and checks global invariants
+    pub fn check(app: &App, contract_addr: &Addr, model: &Model) -> Result<(), TestCaseError> {
+        for user in USERS {
+            let actual: UserInfo = app
+                .wrap()
+                .query_wasm_smart(
+                    contract_addr,
+                    &QueryMsg::GetUser {
+                        user: user.to_string(),
+                        strict: None,
+                    },
+                )
+                .unwrap();
+            let expected = model.positions.get(user).cloned().unwrap_or_default();
+            prop_assert_eq!(
+                actual.total_tokens.u128(),
+                expected.total_tokens,
+                "{}",
+                user
+            );
+            prop_assert_eq!(
+                actual.locked_tokens.u128(),
+                expected.locked_tokens,
+                "{}",
+                user
+            );
+            prop_assert_eq!(actual.voting_power, expected.voting_power, "{}", user);
+            prop_assert_eq!(
+                actual.released_time.seconds(),
+                expected.released_time,
+                "{}",
+                user
+            );
+        }
+
+        for (addr, balances) in &model.bank {
+            for (denom, expected) in balances {
+                let actual = app.wrap().query_balance(addr, denom).unwrap();
+                prop_assert_eq!(actual.amount.u128(), *expected, "{} {}", addr, denom);
+            }
+        }
+
+        let balance = app.wrap().query_balance(contract_addr, DENOM).unwrap();
+        let violations =
+            invariants::check_records(app.dump_wasm_raw(contract_addr), balance.amount).unwrap();
+        prop_assert!(violations.is_empty(), "{:?}", violations);
+        Ok(())
+    }
+
+    pub fn run(ops: &[Op]) -> Result<(), TestCaseError> {
+        let (mut app, contract_addr) = setup();
+        let mut model = Model::new(contract_addr.as_str());
+        model.advance_time(app.block_info().time.seconds());
+        for user in USERS {
+            model.mint(user, DENOM, INITIAL_BALANCE);
+            model.mint(user, OTHER_DENOM, INITIAL_BALANCE);
+        }
+
+        for (step, op) in ops.iter().enumerate() {
+            let expected = apply(&mut model, op);
+            let accepted = execute(&mut app, &contract_addr, op);
+            prop_assert_eq!(accepted, expected, "step {}: {:?}", step, op);
+            check(&app, &contract_addr, &model)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn edge_cases() {
+        use Op::*;
+
+        run(&[
+            Stake {
+                sender: "alice",
+                amount: 0,
+            },
+            Unstake {
+                sender: "alice",
+                amount: 0,
+            },
+            Deposit {
+                sender: "alice",
+                denom: OTHER_DENOM,
+                amount: 100,
+            },
+            Deposit {
+                sender: "alice",
+                denom: DENOM,
+                amount: 0,
+            },
+            Deposit {
+                sender: "alice",
+                denom: DENOM,
+                amount: 100,
+            },
+            Withdraw {
+                sender: "alice",
+                amount: 0,
+            },
+            Stake {
+                sender: "alice",
+                amount: 0,
+            },
+            Unstake {
+                sender: "alice",
+                amount: 0,
+            },
+        ])
+        .unwrap();
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn contract_matches_model(ops in prop::collection::vec(op(), 1..40)) {
+            run(&ops)?;
+        }
+    }
+}